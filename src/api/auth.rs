@@ -5,9 +5,17 @@ use super::*;
 
 pub const BASIC: &str = "Basic ";
 pub const BEARER: &str = "Bearer ";
-const JWT_SECRET: &[u8] = b"secret";
 const ENCODING_ALG: jsonwebtoken::Algorithm = jsonwebtoken::Algorithm::HS512;
 
+/// Settings for issuing and checking access tokens.
+#[derive(Clone)]
+pub struct AuthConfig {
+    /// When set, every request is treated as coming from `STATIC_USER_ID`.
+    pub is_off: bool,
+    pub jwt_secret: Vec<u8>,
+    pub token_ttl_days: i64,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct Claims {
     sub: isize, // user id
@@ -35,13 +43,14 @@ fn token_from_header(headers: &HeaderMap<HeaderValue>, token_prefix: &str) -> Re
     Ok(token.to_owned())
 }
 
-pub fn with_basic_auth(is_auth_off: bool, pool: SqlitePool) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
-    if is_auth_off {
+pub fn with_basic_auth(auth: AuthConfig, pool: SqlitePool) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    if auth.is_off {
         warp::any().and_then(blank_basic_auth).boxed()
     } else {
         warp::header::headers_cloned()
             .map(move |headers: HeaderMap<HeaderValue>| headers)
             .and(with_db_access_manager(pool))
+            .and(warp::any().map(move || auth.clone()))
             .and_then(basic_authorize)
             .boxed()
     }
@@ -51,12 +60,17 @@ async fn blank_basic_auth() -> ZrcSVResult<String> {
     Ok("nothing".to_string())
 }
 
-async fn basic_authorize(headers: HeaderMap<HeaderValue>, conn: DBAccessManager) -> ZrcSVResult<String> {
+async fn basic_authorize(headers: HeaderMap<HeaderValue>, conn: DBAccessManager, auth: AuthConfig) -> ZrcSVResult<String> {
     let user_id = match check_basic_token(headers, conn) {
         Ok(id) => id,
-        Err(e) => return Err(warp::reject::custom(e)),
+        Err(e) => {
+            crate::metrics::login_attempted(false);
+            return Err(warp::reject::custom(e));
+        }
     };
-    let jwt = match create_jwt(user_id) {
+    crate::metrics::login_attempted(true);
+    crate::logging::set_user_id(user_id);
+    let jwt = match create_jwt(user_id, &auth) {
         Ok(t) => t,
         Err(e) => return Err(warp::reject::custom(e)),
     };
@@ -68,18 +82,15 @@ fn check_basic_token(headers: HeaderMap<HeaderValue>, conn: DBAccessManager) ->
     let token_bytes = base64::decode(token.as_bytes())
         .map_err(|e| ZrcSVError::InvalidToken(format!("decoding error, {}", e)))?;
     let token = String::from_utf8_lossy(&token_bytes).to_string();
-    log::debug!("decoded basic token: {}", token);
-    
+
     let parts: Vec<&str> = token.split(":").collect();
     if parts.len() != 2 {
         return Err(ZrcSVError::InvalidToken("invalid token format".to_string()))
     }
     let (name, pwd) = (parts[0], parts[1]);
-    log::debug!("email/user name: {}, password: {}", name, pwd);
+    log::debug!("login attempt of email/user name: {}", name);
     let pwd_hash = hash_pwd(pwd);
-    log::debug!("password hash: {}", pwd_hash);
 
-    
     let user_id = conn.login(name, &pwd_hash).map_err(|e| match e {
         ZrcDBError::DataNotFound(_) => ZrcSVError::UserNotFound,
         _ => ZrcSVError::DBError(e)
@@ -87,9 +98,9 @@ fn check_basic_token(headers: HeaderMap<HeaderValue>, conn: DBAccessManager) ->
     Ok(user_id)
 }
 
-pub fn create_jwt(user_id: isize) -> Result<String, ZrcSVError> {
+pub fn create_jwt(user_id: isize, auth: &AuthConfig) -> Result<String, ZrcSVError> {
     let expiration = Utc::now()
-        .checked_add_signed(chrono::Duration::days(10))
+        .checked_add_signed(chrono::Duration::days(auth.token_ttl_days))
         .expect("valid timestamp")
         .timestamp();
 
@@ -98,15 +109,16 @@ pub fn create_jwt(user_id: isize) -> Result<String, ZrcSVError> {
         exp: expiration as usize,
     };
     let header = Header::new(ENCODING_ALG);
-    encode(&header, &claims, &EncodingKey::from_secret(JWT_SECRET)).map_err(|_| ZrcSVError::JWTTokenCreationError)
+    encode(&header, &claims, &EncodingKey::from_secret(&auth.jwt_secret)).map_err(|_| ZrcSVError::JWTTokenCreationError)
 }
 
-pub fn with_auth(is_auth_off: bool) -> impl Filter<Extract = (isize,), Error = warp::Rejection> + Clone {
-    if is_auth_off {
+pub fn with_auth(auth: AuthConfig) -> impl Filter<Extract = (isize,), Error = warp::Rejection> + Clone {
+    if auth.is_off {
         warp::any().and_then(blank_auth).boxed()
     } else {
         warp::header::headers_cloned()
         .map(move |headers: HeaderMap<HeaderValue>| headers)
+        .and(warp::any().map(move || auth.clone()))
         .and_then(authorize)
         .boxed()
     }
@@ -114,19 +126,21 @@ pub fn with_auth(is_auth_off: bool) -> impl Filter<Extract = (isize,), Error = w
 }
 
 async fn blank_auth() -> ZrcSVResult<isize> {
+    crate::logging::set_user_id(STATIC_USER_ID);
     Ok(STATIC_USER_ID)
 }
 
-async fn authorize(headers: HeaderMap<HeaderValue>) -> ZrcSVResult<isize> {
+async fn authorize(headers: HeaderMap<HeaderValue>, auth: AuthConfig) -> ZrcSVResult<isize> {
     match token_from_header(&headers, BEARER) {
         Ok(jwt) => {
             let decoded = decode::<Claims>(
                 &jwt,
-                &DecodingKey::from_secret(JWT_SECRET),
+                &DecodingKey::from_secret(&auth.jwt_secret),
                 &Validation::new(ENCODING_ALG),
             )
             .map_err(|e| warp::reject::custom(ZrcSVError::InvalidToken(format!("{}", e))))?;
 
+            crate::logging::set_user_id(decoded.claims.sub);
             Ok(decoded.claims.sub)
         }
         Err(e) => return Err(warp::reject::custom(e)),
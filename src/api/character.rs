@@ -37,7 +37,8 @@ pub async fn toggle_uncap(
     user_id: isize,
     conn: DBAccessManager,
 ) -> ZrcSVResult<impl warp::Reply> {
-    let stats = conn.toggle_uncap(user_id, part_id).unwrap();
+    let stats = conn.toggle_uncap(user_id, part_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
     let json = warp::reply::json(&ResponseContainer {
         success: true,
         value: ToggleResult {
@@ -49,3 +50,30 @@ pub async fn toggle_uncap(
     });
     Ok(json)
 }
+
+#[derive(Serialize)]
+pub struct UncapResult {
+    user_id: isize,
+    character: data_access::CharacterStatses,
+    cores: Vec<data_access::CoreInfo>,
+}
+
+// POST /user/me/characters/<part_id>/uncap
+pub async fn uncap_character(
+    part_id: isize,
+    user_id: isize,
+    mut conn: DBAccessManager,
+) -> ZrcSVResult<impl warp::Reply> {
+    let (stats, cores) = conn.uncap_character(user_id, part_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: UncapResult {
+            user_id,
+            character: stats,
+            cores,
+        },
+        error_code: 0,
+        error_msg: String::new(),
+    })
+}
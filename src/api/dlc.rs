@@ -1,19 +1,218 @@
 use super::*;
+use chrono::offset::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use warp::http::header::{HeaderMap, HeaderValue, CACHE_CONTROL, ETAG, HOST, LAST_MODIFIED};
+use warp::http::StatusCode;
+use warp::Reply;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Settings for building download URL of DLC files.
+#[derive(Clone)]
+pub struct DLUrlConfig {
+    /// Public URL of static file directory, such as
+    /// `https://cdn.example.com/zrc/static`. When not set, URL is derived from
+    /// request headers, hostname and port.
+    pub base_url: Option<String>,
+    pub hostname: String,
+    pub port: u16,
+    /// Whether server itself serves HTTPS, decides scheme and default port
+    /// when URL is derived from request.
+    pub is_tls: bool,
+    pub prefix_all: String,
+    pub prefix_static_file: String,
+    pub songs_dirname: String,
+    /// Key used for signing download URLs.
+    pub secret: Vec<u8>,
+    /// Seconds before a signed download URL expires.
+    pub url_ttl: i64,
+    /// Max number of songs a user can download in 24 hours, 0 for no limit.
+    pub daily_limit: usize,
+}
+
+/// Signature part of a download URL's query string.
+pub struct DLSignature {
+    uid: isize,
+    exp: i64,
+    sig: String,
+}
+
+impl DLUrlConfig {
+    /// Base URL for files under static file directory, without trailing slash.
+    /// Headers set by reverse proxy (`X-Forwarded-Proto`, `X-Forwarded-Host`)
+    /// take precedence over `Host` header.
+    pub fn base_url(&self, headers: &HeaderMap<HeaderValue>) -> String {
+        if let Some(base_url) = &self.base_url {
+            return base_url.trim_end_matches('/').to_string();
+        }
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let (default_scheme, default_port) = if self.is_tls { ("https", 443) } else { ("http", 80) };
+        let scheme = header_value("x-forwarded-proto").unwrap_or_else(|| default_scheme.to_string());
+        let host = header_value("x-forwarded-host")
+            .or_else(|| header_value(HOST.as_str()))
+            .unwrap_or_else(|| match self.port {
+                port if port == default_port => self.hostname.clone(),
+                port => format!("{}:{}", self.hostname, port),
+            });
+        let path = [&self.prefix_all, &self.prefix_static_file]
+            .iter()
+            .map(|p| p.trim_matches('/'))
+            .filter(|p| !p.is_empty())
+            .collect::<Vec<&str>>()
+            .join("/");
+        format!("{}://{}/{}", scheme, host, path)
+    }
+
+    fn mac(&self, path: &str, user_id: isize, expire_ts: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts key of any size");
+        mac.update(format!("{}:{}:{}", path, user_id, expire_ts).as_bytes());
+        mac
+    }
+
+    /// Download URL for file at `path` (relative to static file directory),
+    /// signed for given user and valid for `url_ttl` seconds.
+    pub fn signed_url(&self, base_url: &str, path: &str, user_id: isize) -> String {
+        let expire_ts = Utc::now().timestamp() + self.url_ttl;
+        let sig = self.mac(path, user_id, expire_ts).finalize().into_bytes();
+        format!(
+            "{}/{}?uid={}&exp={}&sig={}",
+            base_url,
+            path,
+            user_id,
+            expire_ts,
+            base64::encode_config(sig, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Check signature of a download request for file at `path`, returns id
+    /// of user the URL was signed for.
+    pub fn verify(&self, path: &str, signature: &DLSignature) -> Result<isize, ZrcSVError> {
+        if signature.exp < Utc::now().timestamp() {
+            return Err(ZrcSVError::DownloadDenied("download link expired".to_string()));
+        }
+        let sig = base64::decode_config(&signature.sig, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| ZrcSVError::DownloadDenied("malformed signature".to_string()))?;
+        self.mac(path, signature.uid, signature.exp)
+            .verify(&sig)
+            .map_err(|_| ZrcSVError::DownloadDenied("invalid signature".to_string()))?;
+        Ok(signature.uid)
+    }
+}
+
+/// Checks request for a file under static file directory. Files under songs
+/// directory need a valid signed URL issued to a user owning the song, and
+/// count against user's daily download limit, other files are public.
+/// Returns stored checksum of requested song file, if any, for use as ETag.
+pub async fn check_file_access(
+    dl_config: DLUrlConfig,
+    path: String,
+    query: HashMap<String, String>,
+    mut conn: DBAccessManager,
+) -> ZrcSVResult<Option<String>> {
+    // normalize path the same way file server resolves it, so that e.g.
+    // `./songs/..` or percent-encoded names can't skip the check
+    let path = percent_encoding::percent_decode_str(&path)
+        .decode_utf8_lossy()
+        .split('/')
+        .filter(|seg| !seg.is_empty() && *seg != ".")
+        .collect::<Vec<&str>>()
+        .join("/");
+    let mut segments = path.split('/');
+    // songs directory may be nested, e.g. `data/songs`
+    let is_song_file = dl_config
+        .songs_dirname
+        .split('/')
+        .filter(|seg| !seg.is_empty())
+        .all(|dir| segments.next() == Some(dir));
+    if !is_song_file {
+        return Ok(None);
+    }
+    let song_id = segments.next().unwrap_or_default();
+    let file_name = segments.next().unwrap_or_default();
+    let signature = match (query.get("uid"), query.get("exp"), query.get("sig")) {
+        (Some(uid), Some(exp), Some(sig)) => DLSignature {
+            uid: uid.parse().map_err(|_| ZrcSVError::ImproperFormValue("uid".to_string(), uid.clone()))?,
+            exp: exp.parse().map_err(|_| ZrcSVError::ImproperFormValue("exp".to_string(), exp.clone()))?,
+            sig: sig.clone(),
+        },
+        _ => return Err(warp::reject::custom(ZrcSVError::DownloadDenied("download link not signed".to_string()))),
+    };
+    let user_id = dl_config.verify(&path, &signature).map_err(warp::reject::custom)?;
+    let owned = conn
+        .is_song_owned(user_id, song_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    if !owned {
+        return Err(warp::reject::custom(ZrcSVError::DownloadDenied(format!("song '{}' not purchased", song_id))));
+    }
+    // counted here rather than when URLs are issued, since client asks for
+    // URLs of every song it doesn't have yet
+    conn.record_downloads(user_id, &[&song_id.to_string()], dl_config.daily_limit)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    conn.get_song_file_checksum(song_id, file_name)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))
+}
+
+/// Adds `ETag` and `Cache-Control` to a song file response, and turns it into
+/// `304 Not Modified` when client already has the same file. Range requests
+/// and `Last-Modified` are handled by file server itself.
+pub fn with_cache_headers(
+    reply: warp::fs::File,
+    checksum: Option<String>,
+    if_none_match: Option<String>,
+    max_age: i64,
+) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    let checksum = match checksum {
+        Some(checksum) if !checksum.is_empty() => checksum,
+        _ => return response,
+    };
+    let etag = format!("\"{}\"", checksum);
+    // `is_some_and` needs a newer toolchain than this crate supports
+    #[allow(clippy::unnecessary_map_or)]
+    let not_modified = if_none_match.map_or(false, |tags| {
+        tags.split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag)
+    });
+    if not_modified {
+        let last_modified = response.headers().get(LAST_MODIFIED).cloned();
+        response = warp::reply::with_status(warp::reply(), StatusCode::NOT_MODIFIED).into_response();
+        if let Some(value) = last_modified {
+            response.headers_mut().insert(LAST_MODIFIED, value);
+        }
+    }
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        headers.insert(ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&format!("private, max-age={}", max_age)) {
+        headers.insert(CACHE_CONTROL, value);
+    }
+    response
+}
 
 // GET /serve/download/me/song?url&sid
 pub async fn get_download_list(
-    // (hostname, prefix_static_file, songs_direname)
-    info_group: (String, String, String),
+    dl_config: DLUrlConfig,
+    headers: HeaderMap<HeaderValue>,
     requests: data_access::DLRequest,
     user_id: isize,
     conn: DBAccessManager,
 ) -> ZrcSVResult<impl warp::Reply> {
+    let base_url = dl_config.base_url(&headers);
     let checksums = conn.get_purchase_dl(
         user_id,
         requests,
-        &info_group.0,
-        &info_group.1,
-        &info_group.2,
+        &dl_config.songs_dirname,
+        &|path| dl_config.signed_url(&base_url, path, user_id),
     ).map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
     let result = ResponseContainer {
         success: true,
@@ -48,3 +247,30 @@ pub async fn purcahse_item(
         Err(e) => Err(warp::reject::custom(ZrcSVError::DBError(e))),
     }
 }
+
+#[derive(Serialize)]
+pub struct RedeemResult {
+    coupon: String,
+    items: Vec<data_access::ItemGrant>,
+}
+
+// POST /purchase/me/redeem
+pub async fn redeem_code(
+    form: HashMap<String, String>,
+    user_id: isize,
+    mut conn: DBAccessManager,
+) -> ZrcSVResult<impl warp::Reply> {
+    let code = get_from_form(&form, "code").map_err(warp::reject::custom)?;
+    let items = conn
+        .redeem_code(user_id, code)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: RedeemResult {
+            coupon: code.clone(),
+            items,
+        },
+        error_code: 0,
+        error_msg: String::new(),
+    })
+}
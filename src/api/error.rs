@@ -75,7 +75,6 @@ const SERIAL_NUMBER_ALREADY_USED: i32 = 505; // 此序列码已被使用
 const ITEM_ALREADY_ACQUIRED: i32 = 506; // 你已拥有了此物品
 
 // Friend list ----------------------------------------------------------------
-#[allow(dead_code)]
 const FRIEND_LIST_FULL: i32 = 601; // 好友列表已满
 #[allow(dead_code)]
 const ALREADY_FRIEND: i32 = 602; // 此用户已是好友
@@ -97,9 +96,7 @@ const ERROR_DURING_DOWNLOADING: i32 = 9801; // 下载歌曲时发生问题，请
 const DEVICE_STORAGE_FULL_ERROR: i32 = 9802; // 保存歌曲时发生问题，请检查设备空间容量
 
 // Data Backup ----------------------------------------------------------------
-#[allow(dead_code)]
 const NO_DATA_ON_CLOUD: i32 = 9905; // 没有在云端发现任何数据
-#[allow(dead_code)]
 const ERROR_DURING_UPDATING_DATA: i32 = 9907; // 更新数据时发生了问题
                                                 // const VERSION_TOO_OLD: i32 = 9908; // 服务器只支持最新的版本，请更新Arcaea
 
@@ -123,18 +120,37 @@ pub enum ZrcSVError {
     ImproperFormValue(String, String),
     #[error("invalid friend code")]
     InvalidFriendCode,
+    #[error("download denied, {0}")]
+    DownloadDenied(String),
 }
 
 impl warp::reject::Reject for ZrcSVError {}
+
+impl ZrcSVError {
+    /// Name of variant, used as label of rejection metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ZrcSVError::DBError(_) => "DBError",
+            ZrcSVError::UserNotFound => "UserNotFound",
+            ZrcSVError::InvalidToken(_) => "InvalidToken",
+            ZrcSVError::JWTTokenCreationError => "JWTTokenCreationError",
+            ZrcSVError::NoAuthHeader => "NoAuthHeader",
+            ZrcSVError::TemplateError(_) => "TemplateError",
+            ZrcSVError::IncompleteForm(_) => "IncompleteForm",
+            ZrcSVError::ImproperFormValue(_, _) => "ImproperFormValue",
+            ZrcSVError::InvalidFriendCode => "InvalidFriendCode",
+            ZrcSVError::DownloadDenied(_) => "DownloadDenied",
+        }
+    }
+}
                                                 
 pub async fn handle_rejection(
     err: warp::Rejection,
 ) -> std::result::Result<impl warp::Reply, Infallible> {
     let (status, message, error_code) = if err.is_not_found() {
         (StatusCode::NOT_FOUND, "not found".to_string(), UNKNOWN_ERROR)
-    } else if let Some(_) = err.find::<warp::reject::MethodNotAllowed>() {
-        (StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string(), UNKNOWN_ERROR)
     } else if let Some(e) = err.find::<ZrcSVError>() {
+        crate::metrics::rejected(e.kind());
         match e {
             ZrcSVError::DBError(e) => handle_dberror(e),
             ZrcSVError::UserNotFound => (StatusCode::FORBIDDEN, format!("user not found, check your user name/email and password"), WRONG_USERNAME_OR_PWD),
@@ -148,7 +164,10 @@ pub async fn handle_rejection(
             ZrcSVError::IncompleteForm(_) => (StatusCode::BAD_REQUEST, format!("{}", e), UNKNOWN_ERROR),
             ZrcSVError::ImproperFormValue(_, _) => (StatusCode::BAD_REQUEST, format!("{}", e), UNKNOWN_ERROR),
             ZrcSVError::InvalidFriendCode => (StatusCode::BAD_REQUEST, format!("{}", e), UNKNOWN_ERROR),
+            ZrcSVError::DownloadDenied(_) => (StatusCode::FORBIDDEN, format!("{}", e), AUTH_FAILED),
         }
+    } else if let Some(_) = err.find::<warp::reject::MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string(), UNKNOWN_ERROR)
     } else {
         log::error!("unhandled error, {:?}", err);
         (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_string(), UNKNOWN_ERROR)
@@ -182,6 +201,27 @@ fn handle_dberror(err: &ZrcDBError) -> (StatusCode, String, i32) {
         ZrcDBError::EmailExists => (StatusCode::CONFLICT, format!("{}", err), EMAIL_ALREADY_USED),
         ZrcDBError::FriendExists => (StatusCode::CONFLICT, format!("{}", err), ALREADY_FRIEND),
         ZrcDBError::SelfFriend => (StatusCode::CONFLICT, format!("{}", err), SELF_FRIEND),
+        ZrcDBError::FriendListFull => (StatusCode::FORBIDDEN, format!("{}", err), FRIEND_LIST_FULL),
+        ZrcDBError::FriendBlocked => (StatusCode::FORBIDDEN, format!("{}", err), FUNCTION_NOT_AVAILABLE),
+        ZrcDBError::NoFriendRequest => (StatusCode::NOT_FOUND, format!("{}", err), UNKNOWN_ERROR),
+        ZrcDBError::NotFriend => (StatusCode::FORBIDDEN, format!("{}", err), FUNCTION_NOT_AVAILABLE),
+        ZrcDBError::ItemAlreadyAcquired => (StatusCode::CONFLICT, format!("{}", err), ITEM_ALREADY_ACQUIRED),
+        ZrcDBError::ItemNotAvailable(_) => (StatusCode::NOT_FOUND, format!("{}", err), GET_ITEM_FAILED),
+        ZrcDBError::NotEnoughTicket => (StatusCode::BAD_REQUEST, format!("{}", err), TRANSICATION_ERROR),
+        ZrcDBError::CorruptedBackup(_) => (StatusCode::BAD_REQUEST, format!("{}", err), ERROR_DURING_UPDATING_DATA),
+        ZrcDBError::CorruptedCloudData(_) => {
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", err), ERROR_DURING_UPDATING_DATA)
+        }
+        ZrcDBError::NoCloudData => (StatusCode::NOT_FOUND, format!("{}", err), NO_DATA_ON_CLOUD),
+        ZrcDBError::NoSuchBackup(_) => (StatusCode::NOT_FOUND, format!("{}", err), NO_DATA_ON_CLOUD),
+        ZrcDBError::SongExists(_) => (StatusCode::CONFLICT, format!("{}", err), UNKNOWN_ERROR),
+        ZrcDBError::DownloadLimitReached(_) => (StatusCode::TOO_MANY_REQUESTS, format!("{}", err), DOWNLOAD_LIMIT_MEETS),
+        ZrcDBError::InvalidSerialNumber => (StatusCode::BAD_REQUEST, format!("{}", err), INVALID_SERIAL_NUMBER),
+        ZrcDBError::SerialNumberUsed => (StatusCode::CONFLICT, format!("{}", err), SERIAL_NUMBER_ALREADY_USED),
+        ZrcDBError::CharacterNotOwned(_) => (StatusCode::FORBIDDEN, format!("{}", err), FUNCTION_NOT_AVAILABLE),
+        ZrcDBError::AlreadyUncapped => (StatusCode::CONFLICT, format!("{}", err), ITEM_ALREADY_ACQUIRED),
+        ZrcDBError::CannotUncap => (StatusCode::BAD_REQUEST, format!("{}", err), FUNCTION_NOT_AVAILABLE),
+        ZrcDBError::NotEnoughCore(_) => (StatusCode::BAD_REQUEST, format!("{}", err), FUNCTION_NOT_AVAILABLE),
     };
     (status, message, error_code)
 }
@@ -10,6 +10,61 @@ struct InfoWithFriendList {
     friends: Vec<UserInfoMinimum>
 }
 
+#[derive(Serialize)]
+struct FriendRequests {
+    incoming: Vec<UserInfoMinimum>,
+    outgoing: Vec<UserInfoMinimum>,
+}
+
+fn friend_id_from_form(form: &HashMap<String, String>) -> Result<isize, warp::Rejection> {
+    let friend_id = get_from_form(form, "friend_id")
+        .map_err(|e| warp::reject::custom(e))?;
+    friend_id.parse::<isize>().map_err(|_| {
+        warp::reject::custom(ZrcSVError::ImproperFormValue("friend_id".to_string(), friend_id.clone()))
+    })
+}
+
+fn respond_friend_list(user_id: isize, conn: &DBAccessManager) -> ZrcSVResult<impl warp::Reply> {
+    let friends = conn.get_friend_list(user_id).map_err(|e| {
+        warp::reject::custom(ZrcSVError::DBError(e))
+    })?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: InfoWithFriendList {
+            user_id,
+            create_at: String::new(),
+            update_at: String::new(),
+            friends,
+        },
+        error_code: 0,
+        error_msg: String::new()
+    })
+}
+
+fn respond_friend_requests(user_id: isize, conn: &DBAccessManager) -> ZrcSVResult<impl warp::Reply> {
+    let (incoming, outgoing) = conn.get_friend_requests(user_id).map_err(|e| {
+        warp::reject::custom(ZrcSVError::DBError(e))
+    })?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: FriendRequests { incoming, outgoing },
+        error_code: 0,
+        error_msg: String::new()
+    })
+}
+
+fn respond_blocked_users(user_id: isize, conn: &DBAccessManager) -> ZrcSVResult<impl warp::Reply> {
+    let blocked = conn.get_blocked_users(user_id).map_err(|e| {
+        warp::reject::custom(ZrcSVError::DBError(e))
+    })?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: blocked,
+        error_code: 0,
+        error_msg: String::new()
+    })
+}
+
 // POST /friend/me/add
 pub async fn add_friend(
     form: HashMap<String, String>,
@@ -27,20 +82,7 @@ pub async fn add_friend(
             _ => warp::reject::custom(ZrcSVError::DBError(e)),
         }
     })?;
-    let friends = conn.get_friend_list(user_id).map_err(|e| {
-        warp::reject::custom(ZrcSVError::DBError(e))
-    })?;
-    respond_ok(ResponseContainer {
-        success: true,
-        value: InfoWithFriendList {
-            user_id,
-            create_at: String::new(),
-            update_at: String::new(),
-            friends,
-        },
-        error_code: 0,
-        error_msg: String::new()
-    })
+    respond_friend_list(user_id, &conn)
 }
 
 // POST /friend/me/delete
@@ -49,26 +91,77 @@ pub async fn delete_friend(
     user_id: isize,
     mut conn: DBAccessManager
 ) -> ZrcSVResult<impl warp::Reply> {
-    let friend_id = get_from_form(&form, "friend_id")
-        .map_err(|e| warp::reject::custom(e))?;
-    let friend_id = friend_id.parse::<isize>().map_err(|_| {
-        warp::reject::custom(ZrcSVError::ImproperFormValue("friend_id".to_string(), friend_id.clone()))
-    })?;
+    let friend_id = friend_id_from_form(&form)?;
     conn.delete_friend(user_id, friend_id).map_err(|e| {
         warp::reject::custom(ZrcSVError::DBError(e))
     })?;
-    let friends = conn.get_friend_list(user_id).map_err(|e| {
+    respond_friend_list(user_id, &conn)
+}
+
+// GET /friend/me/request
+pub async fn friend_requests(
+    user_id: isize,
+    conn: DBAccessManager
+) -> ZrcSVResult<impl warp::Reply> {
+    respond_friend_requests(user_id, &conn)
+}
+
+// POST /friend/me/request/accept
+pub async fn accept_friend_request(
+    form: HashMap<String, String>,
+    user_id: isize,
+    mut conn: DBAccessManager
+) -> ZrcSVResult<impl warp::Reply> {
+    let requester_id = friend_id_from_form(&form)?;
+    conn.accept_friend_request(user_id, requester_id).map_err(|e| {
         warp::reject::custom(ZrcSVError::DBError(e))
     })?;
-    respond_ok(ResponseContainer {
-        success: true,
-        value: InfoWithFriendList {
-            user_id,
-            create_at: String::new(),
-            update_at: String::new(),
-            friends,
-        },
-        error_code: 0,
-        error_msg: String::new()
-    })
+    respond_friend_list(user_id, &conn)
+}
+
+// POST /friend/me/request/reject
+pub async fn reject_friend_request(
+    form: HashMap<String, String>,
+    user_id: isize,
+    conn: DBAccessManager
+) -> ZrcSVResult<impl warp::Reply> {
+    let requester_id = friend_id_from_form(&form)?;
+    conn.reject_friend_request(user_id, requester_id).map_err(|e| {
+        warp::reject::custom(ZrcSVError::DBError(e))
+    })?;
+    respond_friend_requests(user_id, &conn)
+}
+
+// GET /friend/me/block
+pub async fn blocked_users(
+    user_id: isize,
+    conn: DBAccessManager
+) -> ZrcSVResult<impl warp::Reply> {
+    respond_blocked_users(user_id, &conn)
+}
+
+// POST /friend/me/block
+pub async fn block_user(
+    form: HashMap<String, String>,
+    user_id: isize,
+    mut conn: DBAccessManager
+) -> ZrcSVResult<impl warp::Reply> {
+    let target_id = friend_id_from_form(&form)?;
+    conn.block_user(user_id, target_id).map_err(|e| {
+        warp::reject::custom(ZrcSVError::DBError(e))
+    })?;
+    respond_blocked_users(user_id, &conn)
+}
+
+// POST /friend/me/unblock
+pub async fn unblock_user(
+    form: HashMap<String, String>,
+    user_id: isize,
+    conn: DBAccessManager
+) -> ZrcSVResult<impl warp::Reply> {
+    let target_id = friend_id_from_form(&form)?;
+    conn.unblock_user(user_id, target_id).map_err(|e| {
+        warp::reject::custom(ZrcSVError::DBError(e))
+    })?;
+    respond_blocked_users(user_id, &conn)
 }
@@ -28,7 +28,11 @@ pub struct AggregateCall {
 }
 
 // POST /user/
-pub async fn signup(form: HashMap<String, String>, mut conn: DBAccessManager) -> ZrcSVResult<impl warp::Reply> {
+pub async fn signup(
+    form: HashMap<String, String>,
+    mut conn: DBAccessManager,
+    auth: auth::AuthConfig,
+) -> ZrcSVResult<impl warp::Reply> {
     // name=abcd&password=00000000&email=a%40b.com&device_id=4C8C520B-28CF-422A-B773-47126BA5F800&platform=ios
     let name = get_from_form(&form, "name").map_err(
         |e| warp::reject::custom(e)
@@ -50,7 +54,8 @@ pub async fn signup(form: HashMap<String, String>, mut conn: DBAccessManager) ->
     let user_id = conn.signup(name, &pwd_hash, email, device_id).map_err(|e| {
         warp::reject::custom(ZrcSVError::DBError(e))
     })?;
-    let access_token = auth::create_jwt(user_id).map_err(|e| warp::reject::custom(e))?;
+    crate::metrics::signed_up();
+    let access_token = auth::create_jwt(user_id, &auth).map_err(|e| warp::reject::custom(e))?;
 
     respond_ok(ResponseContainer {
         success: true,
@@ -149,8 +154,32 @@ pub async fn single_info(conn: DBAccessManager) -> ZrcSVResult<impl warp::Reply>
 }
 
 // GET /present/me
-pub async fn present_me(_: DBAccessManager) -> ZrcSVResult<impl warp::Reply> {
-    respond_ok(Vec::<()>::new())
+pub async fn present_me(user_id: isize, conn: DBAccessManager) -> ZrcSVResult<impl warp::Reply> {
+    match conn.get_presents(user_id) {
+        Ok(presents) => respond_ok(ResponseContainer {
+            success: true,
+            value: presents,
+            error_code: 0,
+            error_msg: String::new(),
+        }),
+        Err(e) => Err(warp::reject::custom(ZrcSVError::DBError(e)))
+    }
+}
+
+// POST /present/me/claim/:present_id
+pub async fn claim_present(
+    present_id: String,
+    user_id: isize,
+    mut conn: DBAccessManager,
+) -> ZrcSVResult<impl warp::Reply> {
+    let items = conn.claim_present(user_id, &present_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: items,
+        error_code: 0,
+        error_msg: String::new(),
+    })
 }
 
 // GET /user/me
@@ -169,6 +198,15 @@ pub async fn world_map(user_id: isize, conn: DBAccessManager) -> ZrcSVResult<imp
     }
 }
 
+/// On/off settings users can change through `POST /user/me/setting/:option`,
+/// each is a column of `player`.
+const USER_SWITCH_SETTINGS: &[&str] = &[
+    "is_hide_rating",
+    "max_stamina_notification_enabled",
+    // friend requests need approval of this user
+    "is_friend_approval_required",
+];
+
 // POST /user/me/setting/:option
 pub async fn user_setting(
     option: String,
@@ -179,18 +217,20 @@ pub async fn user_setting(
     let value = get_from_form(&setting, "value").map_err(
         |e| warp::reject::custom(e)
     )?;
+    let improper_value = || warp::reject::custom(ZrcSVError::ImproperFormValue("value".to_string(), value.clone()));
     if option == "favorite_character" {
-        // TODO: remove this unwrap
-        let char_id = value.parse::<isize>().unwrap();
+        let char_id = value.parse::<isize>().map_err(|_| improper_value())?;
         if let Err(e) = conn.set_favorite_character(user_id, char_id) {
             return Err(warp::reject::custom(ZrcSVError::DBError(e)));
         };
-    } else {
-        let value = value.parse::<bool>().unwrap();
+    } else if USER_SWITCH_SETTINGS.contains(&option.as_str()) {
+        let value = value.parse::<bool>().map_err(|_| improper_value())?;
         match conn.set_user_setting(user_id, option, value) {
             Err(e) => return Err(warp::reject::custom(ZrcSVError::DBError(e))),
             Ok(_) => {}
         };
+    } else {
+        return Err(warp::reject::custom(ZrcSVError::ImproperFormValue("option".to_string(), option)));
     }
     let info = match conn.get_user_info(user_id) {
         Ok(info) => info,
@@ -0,0 +1,129 @@
+use super::*;
+use std::convert::Infallible;
+use warp::http::header::CONTENT_TYPE;
+use warp::http::{Response, StatusCode};
+
+/// Settings of Prometheus metrics endpoint.
+#[derive(Clone)]
+pub struct MetricsConfig {
+    pub is_enabled: bool,
+    /// Path segment endpoint is served at, not affected by API prefix.
+    pub path: String,
+    /// Scrapers need to send `Authorization: Bearer <token>`.
+    pub token: String,
+}
+
+/// Filter setting `route` as label of request metrics, put right after path
+/// of each route. Requests matching no path are counted as `unmatched`, so
+/// random URLs don't create new series.
+pub fn label(route: &str) -> impl Filter<Extract = (), Error = Infallible> + Clone {
+    let route: Arc<str> = Arc::from(route);
+    warp::any()
+        .map(move || crate::logging::set_route(route.clone()))
+        .untuple_one()
+}
+
+/// Label of request being handled, as set by `label`.
+pub fn route_label() -> String {
+    crate::logging::current_route()
+        .map(|route| route.to_string())
+        .unwrap_or_else(|| "unmatched".to_string())
+}
+
+// GET /<metrics_path>
+pub async fn serve_metrics(
+    authorization: Option<String>,
+    config: MetricsConfig,
+    pool: SqlitePool,
+) -> ZrcSVResult<impl warp::Reply> {
+    if !config.is_enabled {
+        return Err(warp::reject::not_found());
+    }
+    let is_authorized = authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix(auth::BEARER))
+        == Some(config.token.as_str());
+    let response = if !is_authorized {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("www-authenticate", "Bearer")
+            .body("unauthorized".to_string())
+    } else {
+        match crate::metrics::render(&pool) {
+            Ok(body) => Response::builder()
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(body),
+            Err(e) => {
+                log::error!("failed to render metrics, {}", e);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(String::new())
+            }
+        }
+    };
+    Ok(response.expect("response with valid headers"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Label `path` gets when requested with `method` from API served under
+    /// prefix `api`.
+    async fn label_of(method: &str, path: &str) -> String {
+        let pool = Arc::new(Pool::new(SqliteConnectionManager::memory()).unwrap());
+        let dl_config = DLUrlConfig {
+            base_url: None,
+            hostname: "localhost".to_string(),
+            port: 8080,
+            is_tls: false,
+            prefix_all: "api".to_string(),
+            prefix_static_file: "static".to_string(),
+            songs_dirname: "songs".to_string(),
+            secret: vec![0; 32],
+            url_ttl: 3600,
+            daily_limit: 0,
+        };
+        let auth = AuthConfig {
+            is_off: true,
+            jwt_secret: Vec::new(),
+            token_ttl_days: 10,
+        };
+        let metrics_config = MetricsConfig {
+            is_enabled: false,
+            path: "metrics".to_string(),
+            token: String::new(),
+        };
+        let routes = api_filter(
+            pool,
+            std::env::temp_dir(),
+            "api".to_string(),
+            "static".to_string(),
+            dl_config,
+            10,
+            auth,
+            metrics_config,
+        );
+        let request = warp::test::request().method(method).path(path);
+        crate::logging::with_request(String::new(), async move {
+            request.reply(&routes).await;
+            route_label()
+        })
+        .await
+        .0
+    }
+
+    #[tokio::test]
+    async fn labels() {
+        assert_eq!(label_of("GET", "/api/score/123").await, "/score/:user_id");
+        assert_eq!(label_of("GET", "/api/score/token").await, "/score/token");
+        assert_eq!(label_of("POST", "/api/user/me/character").await, "/user/me/character");
+        assert_eq!(label_of("POST", "/api/present/me/claim/abc").await, "/present/me/claim/:present_id");
+        assert_eq!(label_of("GET", "/api/user/me/save/history").await, "/user/me/save/history");
+        assert_eq!(label_of("GET", "/api/static/songs/a/base.ogg").await, "/static/*");
+        assert_eq!(label_of("GET", "/metrics").await, "/metrics");
+        assert_eq!(label_of("GET", "/apix/user/info").await, "unmatched");
+        assert_eq!(label_of("GET", "/user/info").await, "unmatched");
+        assert_eq!(label_of("GET", "/api/no/such/route").await, "unmatched");
+    }
+}
@@ -9,10 +9,14 @@ mod dlc;
 pub mod error;
 mod friend;
 mod info;
+mod metrics;
 mod save;
 mod score;
 
 use auth::with_auth;
+pub use auth::AuthConfig;
+pub use dlc::DLUrlConfig;
+pub use metrics::MetricsConfig;
 use error::ZrcSVError;
 
 type ZrcSVResult<T> = std::result::Result<T, warp::Rejection>;
@@ -41,47 +45,55 @@ fn get_from_form<'a>(form: &'a HashMap<String, String>, key: &str) -> Result<&'a
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn api_filter(
     pool: SqlitePool,
-    hostname: String,
     document_root: std::path::PathBuf,
     prefix: String,
     prefix_static_file: String,
-    songs_dirname: String,
-    is_auth_off: bool,
+    dl_config: DLUrlConfig,
+    save_history: usize,
+    auth: AuthConfig,
+    metrics_config: MetricsConfig,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
-    let welcome = warp::path("welcome").map(|| "Welcome to Zrcaea Server");
-    let file_server = warp::path(prefix_static_file.clone())
-        .and(with_auth(true))
-        .and(warp::fs::dir(document_root))
-        .map(|_, it| it);
-    let signup_route = signup(pool.clone());
-    let login_auth = login(is_auth_off, pool.clone());
+    let metrics_route = serve_metrics(metrics_config, pool.clone());
+    let welcome = warp::path("welcome")
+        .and(metrics::label("/welcome"))
+        .map(|| "Welcome to Zrcaea Server");
+    let file_server = static_file(pool.clone(), prefix_static_file, document_root, dl_config.clone());
+    let signup_route = signup(auth.clone(), pool.clone());
+    let login_auth = login(auth.clone(), pool.clone());
     let get_info = game_info(pool.clone())
         .or(pack_info(pool.clone()))
         .or(single_info(pool.clone()))
-        .or(present_me(pool.clone()))
-        .or(score_lookup(pool.clone()));
-    let game_play = aggregate(is_auth_off, pool.clone())
-        .or(user_info(is_auth_off, pool.clone()))
-        .or(world_map(is_auth_off, pool.clone()))
-        .or(user_setting(is_auth_off, pool.clone()))
-        .or(get_download_list(
-            is_auth_off,
-            pool.clone(),
-            hostname.clone(),
-            prefix_static_file.clone(),
-            songs_dirname.clone(),
-        ))
-        .or(purchase_item(is_auth_off, pool.clone()))
-        .or(change_character(is_auth_off, pool.clone()))
-        .or(toggle_uncap(is_auth_off, pool.clone()))
-        .or(score_token(is_auth_off, pool.clone()))
-        .or(score_upload(is_auth_off, pool.clone()))
-        .or(upload_backup_data(is_auth_off, pool.clone()))
-        .or(download_backup_data(is_auth_off, pool.clone()))
-        .or(add_friend(is_auth_off, pool.clone()))
-        .or(delete_friend(is_auth_off, pool.clone()));
+        .or(present_me(auth.clone(), pool.clone()))
+        .or(claim_present(auth.clone(), pool.clone()))
+        .or(score_lookup(pool.clone()))
+        .or(score_compare(auth.clone(), pool.clone()));
+    let game_play = aggregate(auth.clone(), pool.clone())
+        .or(user_info(auth.clone(), pool.clone()))
+        .or(world_map(auth.clone(), pool.clone()))
+        .or(user_setting(auth.clone(), pool.clone()))
+        .or(get_download_list(auth.clone(), pool.clone(), dl_config))
+        .or(purchase_item(auth.clone(), pool.clone()))
+        .or(redeem_code(auth.clone(), pool.clone()))
+        .or(change_character(auth.clone(), pool.clone()))
+        .or(toggle_uncap(auth.clone(), pool.clone()))
+        .or(uncap_character(auth.clone(), pool.clone()))
+        .or(score_token(auth.clone(), pool.clone()))
+        .or(score_upload(auth.clone(), pool.clone()))
+        .or(upload_backup_data(auth.clone(), pool.clone(), save_history))
+        .or(download_backup_data(auth.clone(), pool.clone()))
+        .or(backup_history(auth.clone(), pool.clone()))
+        .or(restore_backup(auth.clone(), pool.clone()))
+        .or(add_friend(auth.clone(), pool.clone()))
+        .or(delete_friend(auth.clone(), pool.clone()))
+        .or(friend_requests(auth.clone(), pool.clone()))
+        .or(accept_friend_request(auth.clone(), pool.clone()))
+        .or(reject_friend_request(auth.clone(), pool.clone()))
+        .or(blocked_users(auth.clone(), pool.clone()))
+        .or(block_user(auth.clone(), pool.clone()))
+        .or(unblock_user(auth.clone(), pool.clone()));
 
     let mut route = welcome
         .or(file_server)
@@ -93,41 +105,100 @@ pub fn api_filter(
     if !prefix.is_empty() {
         route = warp::path(prefix).and(route).boxed();
     }
-    route.recover(api::error::handle_rejection).boxed()
+    metrics_route
+        .or(route)
+        .recover(api::error::handle_rejection)
+        .with(warp::log::custom(|info| {
+            let route = metrics::route_label();
+            crate::metrics::observe_request(&route, info.method().as_str(), info.status().as_u16(), info.elapsed());
+        }))
+        .boxed()
+}
+
+// GET /<metrics_path>
+fn serve_metrics(
+    config: MetricsConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let label = format!("/{}", config.path);
+    warp::path(config.path.clone())
+        .and(warp::path::end())
+        .and(metrics::label(&label))
+        .and(warp::get())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::any().map(move || config.clone()))
+        .and(warp::any().map(move || pool.clone()))
+        .and_then(metrics::serve_metrics)
+}
+
+// ----------------------------------------------------------------------------
+// static file
+
+// GET /<prefix_static_file>/<path>
+fn static_file(
+    pool: SqlitePool,
+    prefix_static_file: String,
+    document_root: std::path::PathBuf,
+    dl_config: DLUrlConfig,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let max_age = dl_config.url_ttl;
+    let label = format!("/{}/*", prefix_static_file);
+    warp::path(prefix_static_file)
+        .and(metrics::label(&label))
+        .and(warp::get())
+        .map(move || dl_config.clone())
+        .and(warp::path::peek())
+        .map(|dl_config, peek: warp::path::Peek| (dl_config, peek.as_str().to_string()))
+        .untuple_one()
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_db_access_manager(pool))
+        .and_then(dlc::check_file_access)
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and(warp::fs::dir(document_root))
+        .map(move |checksum, if_none_match, file| {
+            dlc::with_cache_headers(file, checksum, if_none_match, max_age)
+        })
 }
 
 // ----------------------------------------------------------------------------
 // info
 
 // POST /user/
-fn signup(pool: SqlitePool) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+fn signup(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user")
+        .and(metrics::label("/user"))
         .and(warp::post())
         .and(warp::body::form())
         .and(with_db_access_manager(pool))
+        .and(warp::any().map(move || auth.clone()))
         .and_then(info::signup)
 }
 
 // POST /auth/login
 fn login(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("auth" / "login")
+        .and(metrics::label("/auth/login"))
         .and(warp::post())
-        .and(with_basic_auth(is_auth_off, pool))
+        .and(with_basic_auth(auth, pool))
         .and_then(info::login)
 }
 
 // GET /compose/aggregate?<calls>
 fn aggregate(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("compose" / "aggregate")
+        .and(metrics::label("/compose/aggregate"))
         .and(warp::get())
         .and(warp::query())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(info::aggregate)
 }
@@ -137,6 +208,7 @@ fn game_info(
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("game" / "info")
+        .and(metrics::label("/game/info"))
         .and(warp::get())
         .and(with_db_access_manager(pool))
         .and_then(info::game_info)
@@ -147,6 +219,7 @@ fn pack_info(
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("purchase" / "bundle" / "pack")
+        .and(metrics::label("/purchase/bundle/pack"))
         .and(warp::get())
         .and(with_db_access_manager(pool))
         .and_then(info::pack_info)
@@ -157,6 +230,7 @@ fn single_info(
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("purchase" / "bundle" / "single")
+        .and(metrics::label("/purchase/bundle/single"))
         .and(warp::get())
         .and(with_db_access_manager(pool))
         .and_then(info::single_info)
@@ -164,47 +238,66 @@ fn single_info(
 
 // GET /present/me
 fn present_me(
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("present" / "me")
+        .and(metrics::label("/present/me"))
         .and(warp::get())
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(info::present_me)
 }
 
+// POST /present/me/claim/:present_id
+fn claim_present(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("present" / "me" / "claim" / String)
+        .and(metrics::label("/present/me/claim/:present_id"))
+        .and(warp::post())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(info::claim_present)
+}
+
 // GET /user/info
 fn user_info(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user" / "info")
+        .and(metrics::label("/user/info"))
         .and(warp::get())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(info::user_info)
 }
 
 // GET /world/map/me
 fn world_map(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("world" / "map" / "me")
+        .and(metrics::label("/world/map/me"))
         .and(warp::get())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(info::world_map)
 }
 
 // POST /user/me/setting/:option
 fn user_setting(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user" / "me" / "setting" / String)
+        .and(metrics::label("/user/me/setting/:option"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(info::user_setting)
 }
@@ -214,92 +307,118 @@ fn user_setting(
 
 // GET /serve/download/me/song?url&sid
 fn get_download_list(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
-    hostname: String,
-    prefix_static_file: String,
-    songs_dirname: String,
+    dl_config: DLUrlConfig,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("serve" / "download" / "me" / "song")
+        .and(metrics::label("/serve/download/me/song"))
         .and(warp::get())
-        .map(move || {
-            (
-                hostname.clone(),
-                prefix_static_file.clone(),
-                songs_dirname.clone(),
-            )
-        })
+        .map(move || dl_config.clone())
+        .and(warp::header::headers_cloned())
         .and(warp::query::<DLRequest>())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(dlc::get_download_list)
 }
 
 // POST /purchase/me/pack
 fn purchase_item(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("purchase" / "me" / "pack")
+        .and(metrics::label("/purchase/me/pack"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(dlc::purcahse_item)
 }
 
+// POST /purchase/me/redeem
+fn redeem_code(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("purchase" / "me" / "redeem")
+        .and(metrics::label("/purchase/me/redeem"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(dlc::redeem_code)
+}
+
 // ----------------------------------------------------------------------------
 // character
 
 // POST /user/me/characters
 fn change_character(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user" / "me" / "character")
+        .and(metrics::label("/user/me/character"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(character::change_character)
 }
 
 // POST /user/me/characters/<part_id>/toggle_uncap
 fn toggle_uncap(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user" / "me" / "characters" / isize / "toggle_uncap")
+        .and(metrics::label("/user/me/characters/:part_id/toggle_uncap"))
         .and(warp::post())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(character::toggle_uncap)
 }
 
+// POST /user/me/characters/<part_id>/uncap
+fn uncap_character(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("user" / "me" / "characters" / isize / "uncap")
+        .and(metrics::label("/user/me/characters/:part_id/uncap"))
+        .and(warp::post())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(character::uncap_character)
+}
+
 // ----------------------------------------------------------------------------
 // score
 
 // GET score/token
 fn score_token(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!["score" / "token"]
+        .and(metrics::label("/score/token"))
         .and(warp::get())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(score::score_token)
 }
 
 // POST score/song
 fn score_upload(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!["score" / "song"]
+        .and(metrics::label("/score/song"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(score::score_upload)
 }
@@ -309,62 +428,190 @@ fn score_lookup(
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!["score" / isize]
+        .and(metrics::label("/score/:user_id"))
         .and(warp::get())
         .and(with_db_access_manager(pool))
         .and_then(score::score_lookup)
 }
 
+// GET /score/me/compare/:friend_code
+fn score_compare(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!["score" / "me" / "compare" / isize]
+        .and(metrics::label("/score/me/compare/:friend_code"))
+        .and(warp::get())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(score::score_compare)
+}
+
 // ----------------------------------------------------------------------------
 // data backup
 
 // POST /user/me/save
 fn upload_backup_data(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
+    save_history: usize,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user" / "me" / "save")
+        .and(metrics::label("/user/me/save"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
+        .and(warp::any().map(move || save_history))
         .and_then(save::upload_backup_data)
 }
 
 // GET /user/me/save
 fn download_backup_data(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("user" / "me" / "save")
+        .and(metrics::label("/user/me/save"))
         .and(warp::get())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(save::download_backup_data)
 }
 
+// GET /user/me/save/history
+fn backup_history(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("user" / "me" / "save" / "history")
+        .and(metrics::label("/user/me/save/history"))
+        .and(warp::get())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(save::backup_history)
+}
+
+// POST /user/me/save/restore
+fn restore_backup(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("user" / "me" / "save" / "restore")
+        .and(metrics::label("/user/me/save/restore"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(save::restore_backup)
+}
+
 // POST /friend/me/add
 fn add_friend(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("friend" / "me" / "add")
+        .and(metrics::label("/friend/me/add"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(friend::add_friend)
 }
 
 // POST /friend/me/delete
 fn delete_friend(
-    is_auth_off: bool,
+    auth: AuthConfig,
     pool: SqlitePool,
 ) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
     warp::path!("friend" / "me" / "delete")
+        .and(metrics::label("/friend/me/delete"))
         .and(warp::post())
         .and(warp::body::form())
-        .and(with_auth(is_auth_off))
+        .and(with_auth(auth))
         .and(with_db_access_manager(pool))
         .and_then(friend::delete_friend)
 }
 
+// GET /friend/me/request
+fn friend_requests(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("friend" / "me" / "request")
+        .and(metrics::label("/friend/me/request"))
+        .and(warp::get())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(friend::friend_requests)
+}
+
+// POST /friend/me/request/accept
+fn accept_friend_request(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("friend" / "me" / "request" / "accept")
+        .and(metrics::label("/friend/me/request/accept"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(friend::accept_friend_request)
+}
+
+// POST /friend/me/request/reject
+fn reject_friend_request(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("friend" / "me" / "request" / "reject")
+        .and(metrics::label("/friend/me/request/reject"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(friend::reject_friend_request)
+}
+
+// GET /friend/me/block
+fn blocked_users(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("friend" / "me" / "block")
+        .and(metrics::label("/friend/me/block"))
+        .and(warp::get())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(friend::blocked_users)
+}
+
+// POST /friend/me/block
+fn block_user(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("friend" / "me" / "block")
+        .and(metrics::label("/friend/me/block"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(friend::block_user)
+}
+
+// POST /friend/me/unblock
+fn unblock_user(
+    auth: AuthConfig,
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path!("friend" / "me" / "unblock")
+        .and(metrics::label("/friend/me/unblock"))
+        .and(warp::post())
+        .and(warp::body::form())
+        .and(with_auth(auth))
+        .and(with_db_access_manager(pool))
+        .and_then(friend::unblock_user)
+}
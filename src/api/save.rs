@@ -3,14 +3,17 @@ use data_access::save::BackupData;
 
 // POST /user/me/save
 pub async fn upload_backup_data(
-    mut data: BackupData,
+    form: HashMap<String, String>,
     user_id: isize,
     mut conn: DBAccessManager,
+    save_history: usize,
 ) -> ZrcSVResult<impl warp::Reply> {
+    let mut data = BackupData::from_form(&form)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
     // println!("{}", serde_json::to_string(&data).unwrap());
     data.update_score_on_cloud(&mut conn, user_id)
         .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
-    data.insert_game_progress(&conn, user_id)
+    data.insert_game_progress(&mut conn, user_id, save_history)
         .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
     let mut result = HashMap::new();
     result.insert("user_id", user_id);
@@ -28,23 +31,52 @@ pub async fn download_backup_data(
     conn: DBAccessManager,
 ) -> ZrcSVResult<impl warp::Reply> {
     let mut data = BackupData::new_with_id(user_id);
-    match data.get_game_progress(&conn, user_id) {
-        false => Ok(warp::reply::with_status(
-            r#"{"success":false,"error_code":402}"#.to_string(),
-            warp::http::StatusCode::NOT_FOUND,
-        )),
-        true => {
-            data.get_score_data(&conn, user_id);
-            let container = ResponseContainer {
-                success: true,
-                value: data,
-                error_code: 0,
-                error_msg: String::new(),
-            };
-            Ok(warp::reply::with_status(
-                serde_json::to_string(&container).unwrap(),
-                warp::http::StatusCode::OK,
-            ))
-        }
-    }
+    data.get_game_progress(&conn, user_id)
+        .and_then(|_| data.get_score_data(&conn, user_id))
+        .and_then(|_| data.compute_checksums())
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: data,
+        error_code: 0,
+        error_msg: String::new(),
+    })
+}
+
+// GET /user/me/save/history
+pub async fn backup_history(
+    user_id: isize,
+    conn: DBAccessManager,
+) -> ZrcSVResult<impl warp::Reply> {
+    let history = conn
+        .get_backup_history(user_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    respond_ok(ResponseContainer {
+        success: true,
+        value: history,
+        error_code: 0,
+        error_msg: String::new(),
+    })
+}
+
+// POST /user/me/save/restore
+pub async fn restore_backup(
+    form: HashMap<String, String>,
+    user_id: isize,
+    conn: DBAccessManager,
+) -> ZrcSVResult<impl warp::Reply> {
+    let backup_id = get_from_form(&form, "backup_id").map_err(warp::reject::custom)?;
+    let backup_id = backup_id.parse::<isize>().map_err(|_| {
+        warp::reject::custom(ZrcSVError::ImproperFormValue("backup_id".to_string(), backup_id.clone()))
+    })?;
+    conn.restore_backup(user_id, backup_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    let mut result = HashMap::new();
+    result.insert("backup_id", backup_id);
+    respond_ok(ResponseContainer {
+        success: true,
+        value: result,
+        error_code: 0,
+        error_msg: String::new(),
+    })
 }
@@ -1,5 +1,5 @@
 use super::*;
-use crate::data_access::LookupedScore;
+use crate::data_access::{ComparedScore, LookupedScore};
 
 use askama::Template;
 
@@ -23,7 +23,10 @@ pub async fn score_upload(
     user_id: isize,
     mut conn: DBAccessManager,
 ) -> ZrcSVResult<impl warp::Reply> {
-    let result = conn.score_upload(&score_record, user_id, None).unwrap();
+    let result = conn
+        .play_upload(&score_record, user_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    crate::metrics::score_uploaded();
     respond_ok(ResponseContainer {
         success: true,
         value: result,
@@ -32,6 +35,17 @@ pub async fn score_upload(
     })
 }
 
+/// Format user code as three groups of digits, e.g. `000 123 456`.
+fn format_user_code(user_code: isize) -> String {
+    format!("{:0>9}", user_code)
+        .chars()
+        .collect::<Vec<char>>()
+        .chunks(3)
+        .map(|c| c.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
 #[derive(Template)]
 #[template(path = "score_page.html")]
 struct RecordsTemplate {
@@ -60,13 +74,7 @@ pub async fn score_lookup(user_id: isize, conn: DBAccessManager) -> ZrcSVResult<
             let rating_level = user_info.get_rating_level();
             let template = RecordsTemplate {
                 user_name: user_info.name,
-                user_code: format!("{:0>9}", user_info.user_code)
-                    .chars()
-                    .collect::<Vec<char>>()
-                    .chunks(3)
-                    .map(|c| c.iter().collect::<String>())
-                    .collect::<Vec<String>>()
-                    .join(" "),
+                user_code: format_user_code(user_info.user_code),
                 rating_integer: user_info.rating / 100,
                 rating_fraction: user_info.rating % 100,
                 rating_level,
@@ -81,3 +89,66 @@ pub async fn score_lookup(user_id: isize, conn: DBAccessManager) -> ZrcSVResult<
         }
     }
 }
+
+struct ComparedUser {
+    name: String,
+    code: String,
+    /// Rating formatted as `12.34`, empty if user hides rating.
+    rating: String,
+}
+
+impl ComparedUser {
+    fn new(conn: &DBAccessManager, user_id: isize) -> ZrcSVResult<Self> {
+        let user_info = conn.get_minimum_user_info(user_id)
+            .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+        let rating = if user_info.is_hide_rating {
+            String::new()
+        } else {
+            format!("{}.{:02}", user_info.rating / 100, user_info.rating % 100)
+        };
+        Ok(ComparedUser {
+            name: user_info.name,
+            code: format_user_code(user_info.user_code),
+            rating,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "score_compare.html")]
+struct CompareTemplate {
+    me: ComparedUser,
+    friend: ComparedUser,
+    wins: usize,
+    losses: usize,
+    only_mine: usize,
+    only_theirs: usize,
+    records: Vec<ComparedScore>,
+}
+
+// GET /score/me/compare/:friend_code
+pub async fn score_compare(
+    friend_code: isize,
+    user_id: isize,
+    conn: DBAccessManager,
+) -> ZrcSVResult<impl warp::Reply> {
+    let friend_id = conn.get_user_id_by_code(friend_code).map_err(|e| match e {
+        ZrcDBError::DataNotFound(_) => warp::reject::custom(ZrcSVError::InvalidFriendCode),
+        _ => warp::reject::custom(ZrcSVError::DBError(e)),
+    })?;
+    let records = conn
+        .score_compare(user_id, friend_id)
+        .map_err(|e| warp::reject::custom(ZrcSVError::DBError(e)))?;
+    let deltas = records.iter().filter_map(|record| record.score_delta());
+    let template = CompareTemplate {
+        me: ComparedUser::new(&conn, user_id)?,
+        friend: ComparedUser::new(&conn, friend_id)?,
+        wins: deltas.clone().filter(|delta| *delta > 0).count(),
+        losses: deltas.filter(|delta| *delta < 0).count(),
+        only_mine: records.iter().filter(|record| record.theirs.is_none()).count(),
+        only_theirs: records.iter().filter(|record| record.mine.is_none()).count(),
+        records,
+    };
+    let res = template.render().map_err(|e| warp::reject::custom(ZrcSVError::TemplateError(e)))?;
+    Ok(warp::reply::html(res))
+}
@@ -0,0 +1,552 @@
+//! Parser for Arcaea `.aff` chart files.
+//!
+//! A chart starts with header lines like `AudioOffset:0`, followed by a line
+//! with a single `-` and then one event per line:
+//!
+//! ```text
+//! timing(0,126.00,4.00);
+//! (1000,2);
+//! hold(1000,2000,3);
+//! arc(1000,2000,0.00,1.00,s,1.00,1.00,0,none,true)[arctap(1500)];
+//! timinggroup(noinput){
+//!   timing(0,126.00,4.00);
+//! };
+//! ```
+use std::path::Path;
+use thiserror::Error;
+
+const ARC_EASINGS: [&str; 8] = ["b", "s", "si", "so", "sisi", "siso", "sosi", "soso"];
+/// Max distance between end of an arc and start of the next one for them to
+/// be treated as connected.
+const ARC_CONNECT_DISTANCE: f64 = 0.1;
+
+#[derive(Error, Debug)]
+pub enum ChartError {
+    #[error("failed to read chart, {0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {0}: {1}")]
+    Syntax(usize, String),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+type ChartResult<T> = Result<T, ChartError>;
+
+#[derive(Debug, Clone)]
+pub struct Timing {
+    pub time: i64,
+    pub bpm: f64,
+    pub beats: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Arc {
+    pub start: i64,
+    pub end: i64,
+    pub x_start: f64,
+    pub x_end: f64,
+    pub easing: String,
+    pub y_start: f64,
+    pub y_end: f64,
+    pub color: i32,
+    /// Trace arcs can't be held, they only carry arctaps.
+    pub skyline: bool,
+    pub arctaps: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Note {
+    Tap { time: i64, lane: i8 },
+    Hold { start: i64, end: i64, lane: i8 },
+    Arc(Arc),
+}
+
+impl Note {
+    /// Time when this note is over.
+    pub fn end_time(&self) -> i64 {
+        match self {
+            Note::Tap { time, .. } => *time,
+            Note::Hold { end, .. } => *end,
+            Note::Arc(arc) => arc.end,
+        }
+    }
+}
+
+/// Notes sharing the same timing events. Every chart has a main group, and
+/// may have more declared with `timinggroup`.
+#[derive(Debug, Clone, Default)]
+pub struct TimingGroup {
+    /// Notes in group with `noinput` attribute are for display only.
+    pub no_input: bool,
+    pub timings: Vec<Timing>,
+    pub notes: Vec<Note>,
+}
+
+impl TimingGroup {
+    /// BPM in effect at given time.
+    fn bpm_at(&self, time: i64) -> f64 {
+        self.timings
+            .iter()
+            .filter(|t| t.time <= time)
+            .max_by_key(|t| t.time)
+            .or_else(|| self.timings.first())
+            .map_or(0., |t| t.bpm)
+    }
+
+    /// Number of judgements for a long note, game judges it every half beat
+    /// (or every beat if BPM is 255 or higher). `has_head` is false for arcs
+    /// continuing from a previous arc.
+    fn long_note_combo(&self, start: i64, end: i64, has_head: bool, density_factor: f64) -> usize {
+        let bpm = self.bpm_at(start).abs();
+        if end <= start || bpm == 0. || density_factor <= 0. {
+            return 0;
+        }
+        let interval = 60000. / bpm / if bpm >= 255. { 1. } else { 2. } / density_factor;
+        let total = ((end - start) as f64 / interval) as usize;
+        let first = if has_head { 1 } else { 0 };
+        if first >= total {
+            1
+        } else {
+            total - first
+        }
+    }
+
+    fn combo(&self, density_factor: f64) -> usize {
+        if self.no_input {
+            return 0;
+        }
+        let arcs: Vec<&Arc> = self
+            .notes
+            .iter()
+            .filter_map(|note| match note {
+                Note::Arc(arc) => Some(arc),
+                _ => None,
+            })
+            .collect();
+        self.notes
+            .iter()
+            .map(|note| match note {
+                Note::Tap { .. } => 1,
+                Note::Hold { start, end, .. } => self.long_note_combo(*start, *end, true, density_factor),
+                Note::Arc(arc) if arc.skyline => arc.arctaps.len(),
+                Note::Arc(arc) => {
+                    let has_head = !arcs.iter().any(|prev| prev.is_followed_by(arc));
+                    self.long_note_combo(arc.start, arc.end, has_head, density_factor)
+                }
+            })
+            .sum()
+    }
+}
+
+impl Arc {
+    fn is_followed_by(&self, other: &Arc) -> bool {
+        !self.skyline
+            && !other.skyline
+            && self.end == other.start
+            && self.color == other.color
+            && (self.x_end - other.x_start).abs() < ARC_CONNECT_DISTANCE
+            && (self.y_end - other.y_start).abs() < ARC_CONNECT_DISTANCE
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chart {
+    pub audio_offset: i64,
+    pub density_factor: f64,
+    /// Main timing group comes first.
+    pub groups: Vec<TimingGroup>,
+}
+
+impl Chart {
+    pub fn load(path: &Path) -> ChartResult<Self> {
+        let source = std::fs::read_to_string(path)?;
+        Chart::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> ChartResult<Self> {
+        let mut lines = source.lines().enumerate().map(|(i, line)| (i + 1, line.trim()));
+
+        let mut audio_offset = None;
+        let mut density_factor = 1.;
+        let mut body_found = false;
+        for (line_num, line) in lines.by_ref() {
+            if line == "-" {
+                body_found = true;
+                break;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| syntax(line_num, "header should be in form of key:value"))?;
+            match key {
+                "AudioOffset" => audio_offset = Some(parse_num(line_num, value)?),
+                "TimingPointDensityFactor" => density_factor = parse_num(line_num, value)?,
+                _ => {}
+            }
+        }
+        if !body_found {
+            return Err(ChartError::Invalid("missing '-' line after headers".to_string()));
+        }
+        let audio_offset = audio_offset
+            .ok_or_else(|| ChartError::Invalid("missing AudioOffset header".to_string()))?;
+
+        let mut groups = vec![TimingGroup::default()];
+        let mut current = 0;
+        for (line_num, line) in lines {
+            if line.is_empty() {
+                continue;
+            }
+            if line == "};" {
+                if current == 0 {
+                    return Err(syntax(line_num, "unmatched '};'"));
+                }
+                current = 0;
+                continue;
+            }
+            if let Some(attrs) = line.strip_prefix("timinggroup(").and_then(|l| l.strip_suffix("){")) {
+                if current != 0 {
+                    return Err(syntax(line_num, "timing groups can't be nested"));
+                }
+                groups.push(TimingGroup {
+                    no_input: attrs.split('_').any(|attr| attr == "noinput"),
+                    ..TimingGroup::default()
+                });
+                current = groups.len() - 1;
+                continue;
+            }
+            let event = line
+                .strip_suffix(';')
+                .ok_or_else(|| syntax(line_num, "event should end with ';'"))?;
+            parse_event(line_num, event, &mut groups[current])?;
+        }
+        if current != 0 {
+            return Err(ChartError::Invalid("timing group not closed".to_string()));
+        }
+
+        for (i, group) in groups.iter().enumerate() {
+            match group.timings.iter().map(|t| t.time).min() {
+                Some(0) => {}
+                _ => return Err(ChartError::Invalid(format!("timing group {} has no timing at 0", i))),
+            }
+        }
+
+        Ok(Chart {
+            audio_offset,
+            density_factor,
+            groups,
+        })
+    }
+
+    /// Max combo of chart, which is also the number of judgements used in
+    /// score calculation.
+    pub fn note_count(&self) -> usize {
+        self.groups.iter().map(|g| g.combo(self.density_factor)).sum()
+    }
+
+    /// Time in milliseconds when the last note ends.
+    pub fn duration(&self) -> i64 {
+        self.groups
+            .iter()
+            .flat_map(|g| g.notes.iter())
+            .map(Note::end_time)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn syntax(line_num: usize, message: &str) -> ChartError {
+    ChartError::Syntax(line_num, message.to_string())
+}
+
+fn parse_num<T: std::str::FromStr>(line_num: usize, value: &str) -> ChartResult<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| syntax(line_num, &format!("invalid number '{}'", value.trim())))
+}
+
+/// Split `name(a,b,c)rest` into `name`, argument list and `rest`.
+fn split_call(line_num: usize, event: &str) -> ChartResult<(String, Vec<String>, String)> {
+    let open = event
+        .find('(')
+        .ok_or_else(|| syntax(line_num, &format!("unknown event '{}'", event)))?;
+    let close = event[open..]
+        .find(')')
+        .map(|i| i + open)
+        .ok_or_else(|| syntax(line_num, "missing ')'"))?;
+    let args = event[open + 1..close].split(',').map(|a| a.trim().to_string()).collect();
+    Ok((
+        event[..open].to_string(),
+        args,
+        event[close + 1..].to_string(),
+    ))
+}
+
+fn expect_args(line_num: usize, name: &str, args: &[String], counts: &[usize]) -> ChartResult<()> {
+    if !counts.contains(&args.len()) {
+        return Err(syntax(
+            line_num,
+            &format!("{} takes {:?} arguments, {} given", name, counts, args.len()),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_lane(line_num: usize, value: &str) -> ChartResult<i8> {
+    let lane = parse_num(line_num, value)?;
+    if !(0..=5).contains(&lane) {
+        return Err(syntax(line_num, &format!("invalid lane {}", lane)));
+    }
+    Ok(lane)
+}
+
+fn parse_event(line_num: usize, event: &str, group: &mut TimingGroup) -> ChartResult<()> {
+    let (name, args, rest) = split_call(line_num, event)?;
+    if name != "arc" && !rest.is_empty() {
+        return Err(syntax(line_num, &format!("unexpected '{}'", rest)));
+    }
+    match name.as_str() {
+        "timing" => {
+            expect_args(line_num, &name, &args, &[3])?;
+            group.timings.push(Timing {
+                time: parse_num(line_num, &args[0])?,
+                bpm: parse_num(line_num, &args[1])?,
+                beats: parse_num(line_num, &args[2])?,
+            });
+        }
+        "" => {
+            expect_args(line_num, "tap", &args, &[2])?;
+            group.notes.push(Note::Tap {
+                time: parse_num(line_num, &args[0])?,
+                lane: parse_lane(line_num, &args[1])?,
+            });
+        }
+        "hold" => {
+            expect_args(line_num, &name, &args, &[3])?;
+            let (start, end) = (parse_num(line_num, &args[0])?, parse_num(line_num, &args[1])?);
+            if end <= start {
+                return Err(syntax(line_num, "hold should end after it starts"));
+            }
+            group.notes.push(Note::Hold {
+                start,
+                end,
+                lane: parse_lane(line_num, &args[2])?,
+            });
+        }
+        "arc" => {
+            expect_args(line_num, &name, &args, &[10, 11])?;
+            let arc = parse_arc(line_num, &args, &rest)?;
+            group.notes.push(Note::Arc(arc));
+        }
+        "camera" | "scenecontrol" => {}
+        _ => return Err(syntax(line_num, &format!("unknown event '{}'", name))),
+    }
+    Ok(())
+}
+
+fn parse_arc(line_num: usize, args: &[String], rest: &str) -> ChartResult<Arc> {
+    let easing = args[4].clone();
+    if !ARC_EASINGS.contains(&easing.as_str()) {
+        return Err(syntax(line_num, &format!("invalid arc easing '{}'", easing)));
+    }
+    let skyline = match args[9].as_str() {
+        "true" => true,
+        "false" => false,
+        other => return Err(syntax(line_num, &format!("invalid skyline flag '{}'", other))),
+    };
+    let mut arc = Arc {
+        start: parse_num(line_num, &args[0])?,
+        end: parse_num(line_num, &args[1])?,
+        x_start: parse_num(line_num, &args[2])?,
+        x_end: parse_num(line_num, &args[3])?,
+        easing,
+        y_start: parse_num(line_num, &args[5])?,
+        y_end: parse_num(line_num, &args[6])?,
+        color: parse_num(line_num, &args[7])?,
+        skyline,
+        arctaps: Vec::new(),
+    };
+    if arc.end < arc.start {
+        return Err(syntax(line_num, "arc should not end before it starts"));
+    }
+
+    if !rest.is_empty() {
+        let taps = rest
+            .strip_prefix('[')
+            .and_then(|r| r.strip_suffix(']'))
+            .ok_or_else(|| syntax(line_num, "arctaps should be enclosed in '[]'"))?;
+        for tap in taps.split("),") {
+            let time = tap
+                .trim()
+                .strip_prefix("arctap(")
+                .map(|t| t.trim_end_matches(')'))
+                .ok_or_else(|| syntax(line_num, &format!("invalid arctap '{}'", tap)))?;
+            let time = parse_num(line_num, time)?;
+            if time < arc.start || time > arc.end {
+                return Err(syntax(line_num, &format!("arctap at {} is outside its arc", time)));
+            }
+            arc.arctaps.push(time);
+        }
+        if !arc.skyline {
+            return Err(syntax(line_num, "arctaps can only be put on skyline arcs"));
+        }
+    }
+    Ok(arc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Chart with given body, at 120 BPM unless body has its own timing.
+    fn parse(body: &str) -> ChartResult<Chart> {
+        Chart::parse(&format!("AudioOffset:0\n-\ntiming(0,120.00,4.00);\n{}", body))
+    }
+
+    fn syntax_line(result: ChartResult<Chart>) -> usize {
+        match result {
+            Err(ChartError::Syntax(line, _)) => line,
+            other => panic!("expected syntax error, got {:?}", other),
+        }
+    }
+
+    fn arc(start: i64, end: i64, x: (f64, f64), y: (f64, f64), color: i32) -> Arc {
+        Arc {
+            start,
+            end,
+            x_start: x.0,
+            x_end: x.1,
+            easing: "s".to_string(),
+            y_start: y.0,
+            y_end: y.1,
+            color,
+            skyline: false,
+            arctaps: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn tap_counts_once() {
+        let chart = parse("(1000,1);\n(1500,4);").unwrap();
+        assert_eq!(chart.note_count(), 2);
+        assert_eq!(chart.duration(), 1500);
+    }
+
+    #[test]
+    fn hold_is_judged_every_half_beat_without_head() {
+        // 120 BPM gives a judgement every 250ms, 4 in a second, first one is the head
+        let chart = parse("hold(1000,2000,2);").unwrap();
+        assert_eq!(chart.note_count(), 3);
+    }
+
+    #[test]
+    fn short_hold_counts_once() {
+        let chart = parse("hold(1000,1100,2);").unwrap();
+        assert_eq!(chart.note_count(), 1);
+    }
+
+    #[test]
+    fn hold_is_judged_every_beat_at_high_bpm() {
+        let chart = Chart::parse("AudioOffset:0\n-\ntiming(0,300.00,4.00);\nhold(1000,2000,2);").unwrap();
+        assert_eq!(chart.note_count(), 4);
+    }
+
+    #[test]
+    fn density_factor_scales_long_note_combo() {
+        let source = "AudioOffset:0\nTimingPointDensityFactor:2\n-\ntiming(0,120.00,4.00);\nhold(1000,2000,2);";
+        let chart = Chart::parse(source).unwrap();
+        assert_eq!(chart.note_count(), 7);
+    }
+
+    #[test]
+    fn connected_arc_has_no_head() {
+        let chart = parse(
+            "arc(1000,2000,0.00,1.00,s,1.00,1.00,0,none,false);\n\
+             arc(2000,3000,1.00,0.00,s,1.00,0.00,0,none,false);",
+        )
+        .unwrap();
+        assert_eq!(chart.note_count(), 3 + 4);
+    }
+
+    #[test]
+    fn arcs_of_different_color_are_not_connected() {
+        let chart = parse(
+            "arc(1000,2000,0.00,1.00,s,1.00,1.00,0,none,false);\n\
+             arc(2000,3000,1.00,0.00,s,1.00,0.00,1,none,false);",
+        )
+        .unwrap();
+        assert_eq!(chart.note_count(), 3 + 3);
+    }
+
+    #[test]
+    fn arc_connection_allows_small_gap() {
+        let first = arc(1000, 2000, (0., 1.), (1., 1.), 0);
+        assert!(first.is_followed_by(&arc(2000, 3000, (1.05, 0.), (1., 1.), 0)));
+        assert!(!first.is_followed_by(&arc(2000, 3000, (1. + ARC_CONNECT_DISTANCE, 0.), (1., 1.), 0)));
+        assert!(!first.is_followed_by(&arc(2000, 3000, (1., 0.), (0.5, 1.), 0)));
+        assert!(!first.is_followed_by(&arc(2001, 3000, (1., 0.), (1., 1.), 0)));
+    }
+
+    #[test]
+    fn skyline_arc_counts_arctaps_only() {
+        let chart = parse("arc(1000,2000,0.00,1.00,s,1.00,1.00,0,none,true)[arctap(1000),arctap(1500)];").unwrap();
+        assert_eq!(chart.note_count(), 2);
+    }
+
+    #[test]
+    fn noinput_timing_group_is_not_counted() {
+        let chart = parse(
+            "(1000,1);\n\
+             timinggroup(noinput){\n\
+             timing(0,120.00,4.00);\n\
+             (1000,2);\n\
+             hold(1000,2000,3);\n\
+             };\n\
+             timinggroup(){\n\
+             timing(0,120.00,4.00);\n\
+             (2000,2);\n\
+             };",
+        )
+        .unwrap();
+        assert_eq!(chart.groups.len(), 3);
+        assert!(chart.groups[1].no_input);
+        assert_eq!(chart.note_count(), 2);
+    }
+
+    #[test]
+    fn syntax_errors_report_line() {
+        // body starts at line 4
+        assert_eq!(syntax_line(parse("(1000,1)")), 4);
+        assert_eq!(syntax_line(parse("(1000,1);\n(1000,7);")), 5);
+        assert_eq!(syntax_line(parse("hold(2000,1000,1);")), 4);
+        assert_eq!(syntax_line(parse("(1000,1);\n};")), 5);
+        assert_eq!(syntax_line(parse("flick(1000,1);")), 4);
+        assert_eq!(syntax_line(parse("arc(1000,2000,0.00,1.00,x,1.00,1.00,0,none,false);")), 4);
+        assert_eq!(
+            syntax_line(parse("arc(1000,2000,0.00,1.00,s,1.00,1.00,0,none,false)[arctap(1500)];")),
+            4
+        );
+        assert_eq!(
+            syntax_line(parse("arc(1000,2000,0.00,1.00,s,1.00,1.00,0,none,true)[arctap(2500)];")),
+            4
+        );
+        assert_eq!(
+            syntax_line(parse("timinggroup(){\ntiming(0,120.00,4.00);\ntiminggroup(){")),
+            6
+        );
+        assert_eq!(syntax_line(Chart::parse("AudioOffset\n-\n")), 1);
+    }
+
+    #[test]
+    fn invalid_charts_are_rejected() {
+        assert!(matches!(Chart::parse("AudioOffset:0\n"), Err(ChartError::Invalid(_))));
+        assert!(matches!(Chart::parse("-\ntiming(0,120.00,4.00);"), Err(ChartError::Invalid(_))));
+        assert!(matches!(Chart::parse("AudioOffset:0\n-\n(1000,1);"), Err(ChartError::Invalid(_))));
+        assert!(matches!(
+            parse("timinggroup(){\ntiming(0,120.00,4.00);"),
+            Err(ChartError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,360 @@
+use super::*;
+use chart::Chart;
+use song_package::SongPackage;
+use std::fs;
+use std::path::PathBuf;
+
+const CODE_CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LENGTH: usize = 10;
+
+/// Management commands running against database instead of starting server.
+#[derive(StructOpt)]
+pub enum Command {
+    #[structopt(name = "gen-code", about = "Generate serial codes players can redeem for items.")]
+    GenCode {
+        #[structopt(short, long, default_value = "1", help = "Number of codes to generate.")]
+        count: usize,
+
+        #[structopt(long, help = "Use this code instead of a random one, only valid when count is 1.")]
+        code: Option<String>,
+
+        #[structopt(long = "max-use", default_value = "1", help = "How many users can redeem each code, 0 for unlimited.")]
+        max_use: usize,
+
+        #[structopt(long = "expire-days", help = "Days before codes expire, never expire if omitted.")]
+        expire_days: Option<i64>,
+
+        #[structopt(long = "item", required = true, help = "Item granted, in form of type:id:amount, e.g. core:core_generic:5, ticket::500.")]
+        items: Vec<ItemGrant>,
+    },
+
+    #[structopt(name = "send-present", about = "Send a present to everyone or to users matching filters.")]
+    SendPresent {
+        #[structopt(long, help = "Id of present, a random one is used if omitted.")]
+        id: Option<String>,
+
+        #[structopt(short, long, default_value = "", help = "Description shown to players.")]
+        description: String,
+
+        #[structopt(long = "expire-days", help = "Days before present expires, never expire if omitted.")]
+        expire_days: Option<i64>,
+
+        #[structopt(long = "item", required = true, help = "Item granted, in form of type:id:amount, e.g. core:core_generic:5, ticket::500.")]
+        items: Vec<ItemGrant>,
+
+        #[structopt(long = "user-code", help = "Only send to user with this user code, can be repeated.")]
+        user_codes: Vec<isize>,
+
+        #[structopt(long = "min-rating", help = "Only send to users with rating (x100) at least this value.")]
+        min_rating: Option<isize>,
+    },
+
+    #[structopt(name = "install-song", about = "Install a song from a folder or zip archive containing song.json, base.ogg, charts (<difficulty>.aff) and jacket (base.jpg).")]
+    InstallSong {
+        #[structopt(parse(from_os_str), help = "Path to song folder or zip archive.")]
+        path: PathBuf,
+
+        #[structopt(long, help = "Pack song is added to, overrides `set` in song.json.")]
+        pack: Option<String>,
+
+        #[structopt(long, conflicts_with = "pack", help = "Sell song as a single instead of adding it to a pack.")]
+        single: bool,
+
+        #[structopt(long, default_value = "100", help = "Price of single in tickets.")]
+        price: isize,
+
+        #[structopt(long, help = "Replace existing song with the same id.")]
+        replace: bool,
+    },
+
+    #[structopt(name = "set-max-friend", about = "Set friend list capacity of a user.")]
+    SetMaxFriend {
+        #[structopt(long = "user-code", help = "User code of the user.")]
+        user_code: isize,
+
+        #[structopt(help = "New capacity of friend list.")]
+        max_friend: usize,
+    },
+
+    #[structopt(name = "chart", about = "Chart file utilities.")]
+    Chart(ChartCommand),
+
+    #[structopt(name = "save", about = "Game progress backup management.")]
+    Save(SaveCommand),
+
+    #[structopt(name = "export-user", about = "Export a user's account data to a JSON archive.")]
+    ExportUser {
+        #[structopt(long = "user-code", help = "User code of the user.")]
+        user_code: isize,
+
+        #[structopt(short, long, parse(from_os_str), help = "File archive is written to, stdout if omitted.")]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "import-user", about = "Create a user from a JSON archive written by `export-user`.")]
+    ImportUser {
+        #[structopt(parse(from_os_str), help = "Path to archive file.")]
+        path: PathBuf,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum ChartCommand {
+    #[structopt(name = "check", about = "Parse .aff charts, reporting syntax errors, note count and duration.")]
+    Check {
+        #[structopt(parse(from_os_str), required = true, help = "Chart files to check.")]
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(StructOpt)]
+pub enum SaveCommand {
+    #[structopt(name = "list", about = "List backup history of a user, latest first.")]
+    List {
+        #[structopt(long = "user-code", help = "User code of the user.")]
+        user_code: isize,
+    },
+
+    #[structopt(name = "restore", about = "Serve an older backup on next download of a user.")]
+    Restore {
+        #[structopt(long = "user-code", help = "User code of the user.")]
+        user_code: isize,
+
+        #[structopt(help = "Id of backup, as shown by `save list`.")]
+        backup_id: isize,
+    },
+}
+
+/// `songs_dir` is songs directory under document root.
+pub fn run(command: Command, pool: SqlitePool, songs_dir: &Path) -> Result<(), String> {
+    let conn = pool.get().map_err(|e| format!("failed to get database connection, {}", e))?;
+    let mut conn = DBAccessManager::new(conn);
+    match command {
+        Command::GenCode {
+            count,
+            code,
+            max_use,
+            expire_days,
+            items,
+        } => gen_code(&mut conn, count, code, max_use, expire_days, &items),
+        Command::SendPresent {
+            id,
+            description,
+            expire_days,
+            items,
+            user_codes,
+            min_rating,
+        } => {
+            let filter = PresentFilter {
+                user_codes,
+                min_rating,
+            };
+            send_present(&mut conn, id, &description, expire_days, &items, &filter)
+        }
+        Command::InstallSong {
+            path,
+            pack,
+            single,
+            price,
+            replace,
+        } => install_song(&mut conn, &path, pack, single, price, replace, songs_dir),
+        Command::SetMaxFriend {
+            user_code,
+            max_friend,
+        } => conn
+            .set_max_friend(user_code, max_friend)
+            .map(|_| println!("friend list capacity of user {} set to {}", user_code, max_friend))
+            .map_err(|e| e.to_string()),
+        Command::Chart(ChartCommand::Check { paths }) => check_charts(&paths),
+        Command::Save(SaveCommand::List { user_code }) => list_backups(&conn, user_code),
+        Command::Save(SaveCommand::Restore {
+            user_code,
+            backup_id,
+        }) => conn
+            .get_user_id_by_code(user_code)
+            .and_then(|user_id| conn.restore_backup(user_id, backup_id))
+            .map(|_| println!("backup {} of user {} restored", backup_id, user_code))
+            .map_err(|e| e.to_string()),
+        Command::ExportUser { user_code, output } => export_user(&conn, user_code, output.as_deref()),
+        Command::ImportUser { path } => import_user(&mut conn, &path),
+    }
+}
+
+fn random_code() -> String {
+    use rand::{thread_rng, Rng};
+
+    let mut rng = thread_rng();
+    (0..CODE_LENGTH)
+        .map(|_| CODE_CHARSET[rng.gen_range(0..CODE_CHARSET.len())] as char)
+        .collect()
+}
+
+fn days_later(days: i64) -> i64 {
+    chrono::Utc::now()
+        .checked_add_signed(chrono::Duration::days(days))
+        .expect("valid timestamp")
+        .timestamp()
+}
+
+fn gen_code(
+    conn: &mut DBAccessManager,
+    count: usize,
+    code: Option<String>,
+    max_use: usize,
+    expire_days: Option<i64>,
+    items: &[ItemGrant],
+) -> Result<(), String> {
+    if code.is_some() && count != 1 {
+        return Err("custom code can only be used when count is 1".to_string());
+    }
+    let expire_at = expire_days.map(days_later);
+
+    for _ in 0..count {
+        let code = match &code {
+            Some(c) => c.trim().to_uppercase(),
+            None => random_code(),
+        };
+        conn.create_redeem_code(&code, max_use, expire_at, items)
+            .map_err(|e| e.to_string())?;
+        println!("{}", code);
+    }
+    Ok(())
+}
+
+fn send_present(
+    conn: &mut DBAccessManager,
+    id: Option<String>,
+    description: &str,
+    expire_days: Option<i64>,
+    items: &[ItemGrant],
+    filter: &PresentFilter,
+) -> Result<(), String> {
+    let id = id.unwrap_or_else(random_code);
+    let expire_ts = expire_days.map(days_later).unwrap_or(0);
+    let receiver_count = conn
+        .send_present(&id, description, expire_ts, items, filter)
+        .map_err(|e| e.to_string())?;
+    match receiver_count {
+        Some(count) => println!("present '{}' sent to {} user(s)", id, count),
+        None => println!("present '{}' sent to everyone", id),
+    }
+    Ok(())
+}
+
+fn install_song(
+    conn: &mut DBAccessManager,
+    path: &Path,
+    pack: Option<String>,
+    single: bool,
+    price: isize,
+    replace: bool,
+    songs_dir: &Path,
+) -> Result<(), String> {
+    let package = SongPackage::load(path)?;
+    let shelf = if single || (pack.is_none() && package.is_single()) {
+        SongShelf::Single(price)
+    } else {
+        match pack.or_else(|| package.pack_name().map(str::to_string)) {
+            Some(pack) => SongShelf::Pack(pack),
+            None => return Err("no pack given, use --pack or --single".to_string()),
+        }
+    };
+    let entry = package.entry(shelf);
+    let song_dir = songs_dir.join(package.song_id());
+    conn.install_song(&entry, replace, || package.write_files(&song_dir))
+        .map_err(|e| e.to_string())?;
+    println!(
+        "song '{}' installed to {} with {} chart(s)",
+        entry.song_id,
+        song_dir.display(),
+        entry.charts.len()
+    );
+    Ok(())
+}
+
+fn check_charts(paths: &[PathBuf]) -> Result<(), String> {
+    let mut failed = 0;
+    for path in paths {
+        match Chart::load(path) {
+            Ok(chart) => {
+                let duration = chart.duration() / 1000;
+                println!(
+                    "{}: {} notes, {}:{:02}",
+                    path.display(),
+                    chart.note_count(),
+                    duration / 60,
+                    duration % 60
+                );
+            }
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} chart(s) invalid", failed, paths.len())),
+    }
+}
+
+fn list_backups(conn: &DBAccessManager, user_code: isize) -> Result<(), String> {
+    let user_id = conn.get_user_id_by_code(user_code).map_err(|e| e.to_string())?;
+    let history = conn.get_backup_history(user_id).map_err(|e| e.to_string())?;
+    if history.is_empty() {
+        println!("no backup found for user {}", user_code);
+        return Ok(());
+    }
+    for entry in history {
+        let created_at = chrono::NaiveDateTime::from_timestamp(entry.created_at / 1000, 0);
+        println!(
+            "{:>6}  {}  version {}  {}{}",
+            entry.backup_id,
+            created_at,
+            entry.version,
+            entry.device_model_name,
+            if entry.is_current { "  (current)" } else { "" },
+        );
+    }
+    Ok(())
+}
+
+fn export_user(conn: &DBAccessManager, user_code: isize, output: Option<&Path>) -> Result<(), String> {
+    let user_id = conn.get_user_id_by_code(user_code).map_err(|e| e.to_string())?;
+    let archive = conn.export_user(user_id).map_err(|e| e.to_string())?;
+    let content = serde_json::to_string_pretty(&archive).map_err(|e| e.to_string())?;
+    match output {
+        Some(path) => {
+            fs::write(path, content).map_err(|e| format!("failed to write {}, {}", path.display(), e))?;
+            eprintln!("user {} exported to {}", user_code, path.display());
+        }
+        None => println!("{}", content),
+    }
+    Ok(())
+}
+
+fn import_user(conn: &mut DBAccessManager, path: &Path) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("failed to read {}, {}", path.display(), e))?;
+    let archive: UserArchive =
+        serde_json::from_str(&content).map_err(|e| format!("invalid archive {}, {}", path.display(), e))?;
+    let report = conn.import_user(&archive).map_err(|e| e.to_string())?;
+    println!(
+        "user '{}' imported with id {}, user code {}",
+        archive.profile.user_name, report.user_id, report.user_code
+    );
+    if report.code_changed {
+        println!(
+            "user code {} is taken on this server, friends need to re-add this user",
+            archive.profile.user_code
+        );
+    }
+    if !report.missing_friends.is_empty() {
+        let codes: Vec<String> = report.missing_friends.iter().map(isize::to_string).collect();
+        println!("friends not found on this server, skipped: {}", codes.join(", "));
+    }
+    if !report.one_sided_friends.is_empty() {
+        let codes: Vec<String> = report.one_sided_friends.iter().map(isize::to_string).collect();
+        println!("mutual friends who need to add this user again: {}", codes.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,516 @@
+use super::*;
+use crate::logging::LogFormat;
+use std::fs;
+
+/// Prefix of environment variables overriding config file, a key is set with
+/// `ZRC_<SECTION>_<KEY>`, e.g. `ZRC_SERVER_PORT=8081`, `ZRC_AUTH_JWT_SECRET=...`.
+const ENV_PREFIX: &str = "ZRC_";
+const HIDDEN: &str = "<hidden>";
+
+/// Server settings, loaded from defaults, then config file, then environment
+/// variables, each overriding the former. Command line flags are applied on
+/// top of that.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub resource: ResourceConfig,
+    pub tls: TlsSection,
+    pub auth: AuthSection,
+    pub dlc: DlcSection,
+    pub rate_limit: RateLimitSection,
+    pub save: SaveSection,
+    pub metrics: MetricsSection,
+    pub log: LogSection,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub ip: String,
+    pub hostname: String,
+    pub port: u16,
+    // final access URL will be http://<hostname>/<prefix_all>/<your-api>
+    pub prefix_all: String,
+    // final access URL will be http://<hostname>/<prefix_all>/<prefix_static>/<your-filename>
+    pub prefix_static: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            ip: "127.0.0.1".to_string(),
+            hostname: "localhost".to_string(),
+            port: 8080,
+            prefix_all: String::new(),
+            prefix_static: "static".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: String,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            path: "./ZrcDB.db".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceConfig {
+    pub document_root: String,
+    pub songs_dirname: String,
+}
+
+impl Default for ResourceConfig {
+    fn default() -> Self {
+        ResourceConfig {
+            document_root: "./".to_string(),
+            songs_dirname: "songs".to_string(),
+        }
+    }
+}
+
+/// HTTPS is served when both certificate and key are given.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    /// PEM file with certificate chain, server certificate first.
+    pub cert_path: Option<String>,
+    /// PEM file with PKCS#8 or RSA private key.
+    pub key_path: Option<String>,
+    /// Port redirecting plain HTTP requests to HTTPS, 0 for no redirect.
+    pub redirect_port: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSection {
+    pub disabled: bool,
+    /// Key for signing access tokens, a random one is generated on each start
+    /// if not set, which logs out every user on restart.
+    pub jwt_secret: Option<String>,
+    pub token_ttl_days: i64,
+}
+
+impl Default for AuthSection {
+    fn default() -> Self {
+        AuthSection {
+            disabled: false,
+            jwt_secret: None,
+            token_ttl_days: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DlcSection {
+    // e.g. https://cdn.example.com:8443/zrc/static, songs are then downloaded from
+    // <base_url>/<songs_dirname>/<song-id>/base.ogg
+    pub base_url: Option<String>,
+    /// Key for signing download URLs, a random one is generated on each start
+    /// if not set.
+    pub secret: Option<String>,
+    pub url_ttl: i64,
+}
+
+impl Default for DlcSection {
+    fn default() -> Self {
+        DlcSection {
+            base_url: None,
+            secret: None,
+            url_ttl: 3600,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSection {
+    /// Max number of songs a user can download in 24 hours, 0 for no limit.
+    /// Counted when song files are served, so it has no effect on files
+    /// served by another host set with `dlc.base_url`.
+    pub daily_downloads: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SaveSection {
+    /// Number of game progress backups kept for each user, at least 1.
+    pub history: usize,
+}
+
+impl Default for SaveSection {
+    fn default() -> Self {
+        SaveSection { history: 10 }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSection {
+    pub enabled: bool,
+    /// Endpoint is served at `/<path>`, regardless of `server.prefix_all`.
+    pub path: String,
+    /// Scrapers need to send `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+}
+
+impl Default for MetricsSection {
+    fn default() -> Self {
+        MetricsSection {
+            enabled: false,
+            path: "metrics".to_string(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSection {
+    pub level: String,
+    /// `text`, `logfmt` or `json`.
+    pub format: String,
+    /// Whether to write a line for each request handled.
+    pub access: bool,
+}
+
+impl Default for LogSection {
+    fn default() -> Self {
+        LogSection {
+            level: "info".to_string(),
+            format: "text".to_string(),
+            access: true,
+        }
+    }
+}
+
+impl Config {
+    /// Load config from file at `path` if given, then apply environment
+    /// variables.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        // variables not in UTF-8 can't be config values and are left alone
+        let vars = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+        Config::load_with_env(path, vars)
+    }
+
+    fn load_with_env(path: Option<&Path>, vars: impl Iterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut value = toml::Value::try_from(Config::default()).map_err(|e| e.to_string())?;
+        if let Some(path) = path {
+            let content = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read config file {}, {}", path.display(), e))?;
+            let file_value: toml::Value = toml::from_str(&content)
+                .map_err(|e| format!("invalid config file {}, {}", path.display(), e))?;
+            merge_value(&mut value, file_value);
+        }
+        apply_env(&mut value, vars)?;
+        value
+            .try_into()
+            .map_err(|e| format!("invalid config, {}", e))
+    }
+
+    /// Check values that can't be expressed by types alone, all problems found
+    /// are reported at once.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        if let Err(e) = self.socket_addr() {
+            errors.push(e);
+        }
+        if self.database.path.is_empty() {
+            errors.push("database.path must not be empty".to_string());
+        }
+        // song files are told apart by this path, so it must be written the
+        // same way as normalized request paths
+        let songs_dirname = &self.resource.songs_dirname;
+        if songs_dirname.is_empty() {
+            errors.push("resource.songs_dirname must not be empty".to_string());
+        } else if songs_dirname.split('/').any(|seg| seg.is_empty() || seg == "." || seg == "..") {
+            errors.push(format!(
+                "invalid resource.songs_dirname '{}', must not contain empty, '.' or '..' segments",
+                self.resource.songs_dirname
+            ));
+        }
+        match (&self.tls.cert_path, &self.tls.key_path) {
+            (Some(_), None) | (None, Some(_)) => {
+                errors.push("tls.cert_path and tls.key_path must be given together".to_string())
+            }
+            (None, None) if self.tls.redirect_port != 0 => {
+                errors.push("tls.redirect_port needs tls.cert_path and tls.key_path".to_string())
+            }
+            _ if self.tls.redirect_port != 0 && self.tls.redirect_port == self.server.port => {
+                errors.push("tls.redirect_port must differ from server.port".to_string())
+            }
+            _ => {}
+        }
+        if let Some(url) = &self.dlc.base_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                errors.push(format!(
+                    "invalid dlc.base_url '{}', must start with http:// or https://",
+                    url
+                ));
+            }
+        }
+        if self.dlc.url_ttl <= 0 {
+            errors.push("dlc.url_ttl must be positive".to_string());
+        }
+        // the backup just uploaded is trimmed with the rest otherwise
+        if self.save.history < 1 {
+            errors.push("save.history must be at least 1".to_string());
+        }
+        if self.auth.token_ttl_days <= 0 {
+            errors.push("auth.token_ttl_days must be positive".to_string());
+        }
+        if matches!(&self.auth.jwt_secret, Some(s) if s.is_empty()) {
+            errors.push("auth.jwt_secret must not be empty".to_string());
+        }
+        if matches!(&self.dlc.secret, Some(s) if s.is_empty()) {
+            errors.push("dlc.secret must not be empty".to_string());
+        }
+        if self.metrics.enabled {
+            if self.metrics.path.is_empty() || self.metrics.path.contains('/') {
+                errors.push("metrics.path must be a single path segment".to_string());
+            }
+            if self.metrics.token.as_deref().unwrap_or("").is_empty() {
+                errors.push("metrics.token is needed when metrics is enabled".to_string());
+            }
+        }
+        if let Err(e) = self.log_level() {
+            errors.push(e);
+        }
+        if let Err(e) = self.log_format() {
+            errors.push(e);
+        }
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("\n\t")),
+        }
+    }
+
+    pub fn socket_addr(&self) -> Result<SocketAddr, String> {
+        format!("{}:{}", self.server.ip, self.server.port)
+            .parse()
+            .map_err(|e| format!("invalid server address, IP: {}, Port: {}, {}", self.server.ip, self.server.port, e))
+    }
+
+    /// Certificate and key paths, if HTTPS is enabled.
+    pub fn tls_paths(&self) -> Option<(&Path, &Path)> {
+        match (&self.tls.cert_path, &self.tls.key_path) {
+            (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+            _ => None,
+        }
+    }
+
+    pub fn log_level(&self) -> Result<log::LevelFilter, String> {
+        self.log
+            .level
+            .parse()
+            .map_err(|_| format!("invalid log.level '{}'", self.log.level))
+    }
+
+    pub fn log_format(&self) -> Result<LogFormat, String> {
+        self.log.format.parse().map_err(|e| format!("invalid log.format, {}", e))
+    }
+
+    /// Config in TOML format with secrets masked, for printing.
+    pub fn display(&self) -> String {
+        let mut masked = self.clone();
+        for secret in [
+            &mut masked.auth.jwt_secret,
+            &mut masked.dlc.secret,
+            &mut masked.metrics.token,
+        ] {
+            if secret.is_some() {
+                *secret = Some(HIDDEN.to_string());
+            }
+        }
+        toml::to_string(&masked).unwrap_or_else(|e| e.to_string())
+    }
+}
+
+/// Recursively overwrite tables in `base` with those in `other`.
+fn merge_value(base: &mut toml::Value, other: toml::Value) {
+    match (base, other) {
+        (toml::Value::Table(base), toml::Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// Set `section.key` for each `ZRC_<SECTION>_<KEY>` variable. Value is taken
+/// as string if existing value is string or unset, otherwise parsed as TOML
+/// value, so `ZRC_SERVER_PORT=8081` gives an integer.
+fn apply_env(value: &mut toml::Value, vars: impl Iterator<Item = (String, String)>) -> Result<(), String> {
+    let sections = match value {
+        toml::Value::Table(t) => t,
+        _ => return Ok(()),
+    };
+    for (name, raw) in vars {
+        let rest = match name.strip_prefix(ENV_PREFIX) {
+            Some(rest) => rest.to_lowercase(),
+            None => continue,
+        };
+        let found = sections.iter_mut().find_map(|(section, table)| {
+            rest.strip_prefix(section.as_str())
+                .and_then(|key| key.strip_prefix('_'))
+                .map(|key| (key.to_string(), table))
+        });
+        let (key, table) = match found {
+            Some((key, toml::Value::Table(table))) => (key, table),
+            _ => continue,
+        };
+        let parsed = match table.get(&key) {
+            None | Some(toml::Value::String(_)) => toml::Value::String(raw),
+            Some(_) => toml::from_str::<toml::Value>(&format!("v = {}", raw))
+                .ok()
+                .and_then(|mut v| v.as_table_mut().and_then(|t| t.remove("v")))
+                .ok_or_else(|| format!("invalid value of {}, '{}'", name, raw))?,
+        };
+        table.insert(key, parsed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Config file in temp directory with `content`, removed when dropped.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("zrc_{}_{}.toml", name, std::process::id()));
+            fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let file = TempFile::new(
+            "config_precedence",
+            "[server]\nhostname = \"file\"\nport = 8000\nip = \"0.0.0.0\"\n[log]\nlevel = \"debug\"",
+        );
+        let env = vars(&[("ZRC_SERVER_PORT", "8001"), ("ZRC_SERVER_IP", "10.0.0.1")]);
+        let mut config = Config::load_with_env(Some(&file.0), env).unwrap();
+        // default < file
+        assert_eq!(config.server.prefix_static, "static");
+        assert_eq!(config.server.hostname, "file");
+        assert_eq!(config.log.level, "debug");
+        // file < env
+        assert_eq!(config.server.port, 8001);
+        assert_eq!(config.server.ip, "10.0.0.1");
+
+        // env < cli
+        let mut cli = Cli::from_iter(&["zrc_server", "--ip", "10.0.0.2", "--log-level", "warn"]);
+        cli.apply_to(&mut config);
+        assert_eq!(config.server.ip, "10.0.0.2");
+        assert_eq!(config.log.level, "warn");
+        assert_eq!(config.server.port, 8001);
+        assert_eq!(config.server.hostname, "file");
+    }
+
+    #[test]
+    fn unknown_file_key() {
+        let file = TempFile::new("config_unknown", "[server]\nprot = 8000");
+        assert!(Config::load_with_env(Some(&file.0), vars(&[])).is_err());
+    }
+
+    #[test]
+    fn merge_nested_tables() {
+        let mut base: toml::Value = toml::from_str("[a]\nx = 1\ny = 2\n[b]\nz = 3").unwrap();
+        let other: toml::Value = toml::from_str("[a]\ny = 20\nw = 40\n[c]\nv = 5").unwrap();
+        merge_value(&mut base, other);
+        let expected: toml::Value = toml::from_str("[a]\nx = 1\ny = 20\nw = 40\n[b]\nz = 3\n[c]\nv = 5").unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn env_value_types() {
+        let mut value = toml::Value::try_from(Config::default()).unwrap();
+        apply_env(
+            &mut value,
+            vars(&[
+                ("ZRC_SERVER_PORT", "9000"),
+                ("ZRC_SERVER_HOSTNAME", "8080"),
+                ("ZRC_AUTH_DISABLED", "true"),
+                ("ZRC_AUTH_JWT_SECRET", "123"),
+                ("ZRC_RATE_LIMIT_DAILY_DOWNLOADS", "5"),
+                ("ZRC_NOT_A_SECTION", "x"),
+                ("OTHER_SERVER_PORT", "1"),
+            ]),
+        )
+        .unwrap();
+        let config: Config = value.try_into().unwrap();
+        assert_eq!(config.server.port, 9000);
+        // string keys stay strings even if value looks like a number
+        assert_eq!(config.server.hostname, "8080");
+        assert!(config.auth.disabled);
+        // unset optional keys are taken as strings
+        assert_eq!(config.auth.jwt_secret.as_deref(), Some("123"));
+        // section names with underscore
+        assert_eq!(config.rate_limit.daily_downloads, 5);
+    }
+
+    #[test]
+    fn songs_dirname() {
+        let mut config = Config::default();
+        for valid in ["songs", "data/songs"] {
+            config.resource.songs_dirname = valid.to_string();
+            assert!(config.validate().is_ok(), "{}", valid);
+        }
+        for invalid in ["", "/songs", "data//songs", "songs/", "./songs", "data/../songs"] {
+            config.resource.songs_dirname = invalid.to_string();
+            assert!(config.validate().unwrap_err().contains("songs_dirname"), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn save_history() {
+        let mut config = Config::default();
+        config.save.history = 1;
+        assert!(config.validate().is_ok());
+        config.save.history = 0;
+        assert!(config.validate().unwrap_err().contains("save.history"));
+    }
+
+    #[test]
+    fn invalid_env_value() {
+        let mut value = toml::Value::try_from(Config::default()).unwrap();
+        let result = apply_env(&mut value, vars(&[("ZRC_SERVER_PORT", "not a port")]));
+        assert!(result.unwrap_err().contains("ZRC_SERVER_PORT"));
+    }
+}
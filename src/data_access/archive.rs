@@ -0,0 +1,539 @@
+use super::*;
+use rusqlite::OptionalExtension;
+
+/// Version of archive format written by export. Archives of a newer version
+/// are refused on import.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Everything about a user that can be moved to another server instance.
+/// IDs local to a database are left out, friends are referred to by user code.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserArchive {
+    pub version: u32,
+    pub exported_at: i64,
+    pub profile: ArchivedProfile,
+    pub settings: ArchivedSettings,
+    #[serde(default)]
+    pub items: ArchivedItems,
+    #[serde(default)]
+    pub purchases: ArchivedPurchases,
+    #[serde(default)]
+    pub partners: Vec<ArchivedPartner>,
+    #[serde(default)]
+    pub world: Vec<ArchivedMapProgress>,
+    #[serde(default)]
+    pub scores: Vec<ArchivedScore>,
+    #[serde(default)]
+    pub recent_scores: Vec<ArchivedRecentScore>,
+    #[serde(default)]
+    pub friends: Vec<ArchivedFriend>,
+    #[serde(default)]
+    pub cloud_save: Option<ArchivedBackup>,
+    #[serde(default)]
+    pub save_history: Vec<ArchivedBackup>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedProfile {
+    pub user_name: String,
+    pub user_code: isize,
+    pub display_name: String,
+    pub email: String,
+    pub pwdhash: String,
+    pub ticket: isize,
+    pub partner: isize,
+    pub is_locked_name_duplicated: bool,
+    pub is_skill_sealed: bool,
+    pub curr_map: String,
+    pub prog_boost: isize,
+    pub stamina: isize,
+    pub next_fragstam_ts: i64,
+    pub max_stamina_ts: i64,
+    pub recent_score_date: i64,
+    pub max_friend: isize,
+    pub rating: isize,
+    pub join_date: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedSettings {
+    pub max_stamina_notification_enabled: bool,
+    pub is_hide_rating: bool,
+    pub favorite_partner: isize,
+    pub is_friend_approval_required: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ArchivedItems {
+    pub cores: Vec<ArchivedCore>,
+    pub fragment: isize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedCore {
+    pub core_type: String,
+    pub amount: isize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ArchivedPurchases {
+    pub packs: Vec<String>,
+    pub singles: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedPartner {
+    pub part_id: isize,
+    pub is_uncapped_override: bool,
+    pub is_uncapped: bool,
+    pub exp_val: f64,
+    pub overdrive: f64,
+    pub prog: f64,
+    pub frag: f64,
+    pub lv: i8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedMapProgress {
+    pub map_id: String,
+    pub curr_capture: isize,
+    pub curr_position: isize,
+    pub is_locked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedScore {
+    pub played_date: i64,
+    pub song_id: String,
+    pub difficulty: i8,
+    pub score: isize,
+    pub shiny_pure: isize,
+    pub pure: isize,
+    pub far: isize,
+    pub lost: isize,
+    pub rating: f64,
+    pub health: i8,
+    pub modifier: isize,
+    pub clear_type: i8,
+    /// Whether this is user's best score on the chart.
+    pub is_best: bool,
+}
+
+/// An entry in user's recent score window, referring to score played at
+/// `played_date`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedRecentScore {
+    pub played_date: i64,
+    pub is_recent_10: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedFriend {
+    pub user_code: isize,
+    /// Whether friend also has user as friend, only user's own side is
+    /// restored on import.
+    pub is_mutual: bool,
+    /// Same as `friend_list.state`, '' for friend, 'pending' for request
+    /// waiting for approval, 'blocked' for blocked user.
+    pub state: String,
+}
+
+/// Game progress backup, `unlocklist` and `story` are kept as JSON text they
+/// are stored in.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedBackup {
+    pub version: isize,
+    pub unlocklist: String,
+    pub installid: String,
+    pub devicemodel_name: String,
+    pub story: String,
+    pub create_at: i64,
+}
+
+/// Result of importing an archive.
+#[derive(Debug)]
+pub struct ImportReport {
+    pub user_id: isize,
+    pub user_code: isize,
+    /// Whether user code in archive is taken on this server and a new one is
+    /// assigned.
+    pub code_changed: bool,
+    /// Codes of friends not found on this server, which are left out.
+    pub missing_friends: Vec<isize>,
+    /// Codes of mutual friends in archive, who need to add user again since
+    /// friend lists of other users are left untouched.
+    pub one_sided_friends: Vec<isize>,
+}
+
+fn is_set(row: &rusqlite::Row, column: &str) -> Result<bool, rusqlite::Error> {
+    Ok(row.get::<&str, String>(column)? == "t")
+}
+
+fn flag(value: bool) -> &'static str {
+    if value {
+        "t"
+    } else {
+        ""
+    }
+}
+
+pub fn export_user(conn: &DBAccessManager, user_id: isize) -> Result<UserArchive, rusqlite::Error> {
+    let conn = &conn.connection;
+    let (profile, settings) = conn.query_row(sql_stmt::ARCHIVE_PLAYER, params![user_id], |row| {
+        let profile = ArchivedProfile {
+            user_name: row.get("user_name")?,
+            user_code: row.get("user_code")?,
+            display_name: row.get("display_name")?,
+            email: row.get("email")?,
+            pwdhash: row.get("pwdhash")?,
+            ticket: row.get("ticket")?,
+            partner: row.get("partner")?,
+            is_locked_name_duplicated: is_set(row, "locked")?,
+            is_skill_sealed: is_set(row, "skill_sealed")?,
+            curr_map: row.get("curr_map")?,
+            prog_boost: row.get("prog_boost")?,
+            stamina: row.get("stamina")?,
+            next_fragstam_ts: row.get("next_fragstam_ts")?,
+            max_stamina_ts: row.get("max_stamina_ts")?,
+            recent_score_date: row.get("recent_score_date")?,
+            max_friend: row.get("max_friend")?,
+            rating: row.get("rating")?,
+            join_date: row.get("join_date")?,
+        };
+        let settings = ArchivedSettings {
+            max_stamina_notification_enabled: is_set(row, "stamina_notification")?,
+            is_hide_rating: is_set(row, "hide_rating")?,
+            favorite_partner: row.get("fav_partner")?,
+            is_friend_approval_required: is_set(row, "approval_required")?,
+        };
+        Ok((profile, settings))
+    })?;
+
+    let items = ArchivedItems {
+        cores: conn
+            .prepare(sql_stmt::GET_USER_CORES)?
+            .query_map(params![user_id], |row| {
+                Ok(ArchivedCore {
+                    core_type: row.get("core_type")?,
+                    amount: row.get("amount")?,
+                })
+            })
+            .and_then(|rows| rows.collect())?,
+        fragment: conn
+            .query_row(sql_stmt::ARCHIVE_FRAGMENT, params![user_id], |row| row.get(0))
+            .optional()?
+            .unwrap_or(0),
+    };
+
+    let purchases = ArchivedPurchases {
+        packs: conn
+            .prepare(sql_stmt::ARCHIVE_PACK_PURCHASE)?
+            .query_map(params![user_id], |row| row.get(0))
+            .and_then(|rows| rows.collect())?,
+        singles: conn
+            .prepare(sql_stmt::ARCHIVE_SINGLE_PURCHASE)?
+            .query_map(params![user_id], |row| row.get(0))
+            .and_then(|rows| rows.collect())?,
+    };
+
+    let partners = conn
+        .prepare(sql_stmt::ARCHIVE_PART_STATS)?
+        .query_map(params![user_id], |row| {
+            Ok(ArchivedPartner {
+                part_id: row.get("part_id")?,
+                is_uncapped_override: is_set(row, "uncapped_override")?,
+                is_uncapped: is_set(row, "uncapped")?,
+                exp_val: row.get("exp_val")?,
+                overdrive: row.get("overdrive")?,
+                prog: row.get("prog")?,
+                frag: row.get("frag")?,
+                lv: row.get("lv")?,
+            })
+        })
+        .and_then(|rows| rows.collect())?;
+
+    let world = conn
+        .prepare(sql_stmt::ARCHIVE_MAP_PROGRESS)?
+        .query_map(params![user_id], |row| {
+            Ok(ArchivedMapProgress {
+                map_id: row.get("map_id")?,
+                curr_capture: row.get("curr_capture")?,
+                curr_position: row.get("curr_position")?,
+                is_locked: is_set(row, "is_locked")?,
+            })
+        })
+        .and_then(|rows| rows.collect())?;
+
+    let scores = conn
+        .prepare(sql_stmt::ARCHIVE_SCORES)?
+        .query_map(params![user_id], |row| {
+            Ok(ArchivedScore {
+                played_date: row.get("played_date")?,
+                song_id: row.get("song_id")?,
+                difficulty: row.get("difficulty")?,
+                score: row.get("score")?,
+                shiny_pure: row.get("shiny_pure")?,
+                pure: row.get("pure")?,
+                far: row.get("far")?,
+                lost: row.get("lost")?,
+                rating: row.get("rating")?,
+                health: row.get("health")?,
+                modifier: row.get("modifier")?,
+                clear_type: row.get("clear_type")?,
+                is_best: row.get("is_best")?,
+            })
+        })
+        .and_then(|rows| rows.collect())?;
+
+    let recent_scores = conn
+        .prepare(sql_stmt::ARCHIVE_RECENT_SCORES)?
+        .query_map(params![user_id], |row| {
+            Ok(ArchivedRecentScore {
+                played_date: row.get("played_date")?,
+                is_recent_10: is_set(row, "is_recent_10")?,
+            })
+        })
+        .and_then(|rows| rows.collect())?;
+
+    let friends = conn
+        .prepare(sql_stmt::ARCHIVE_FRIENDS)?
+        .query_map(params![user_id], |row| {
+            Ok(ArchivedFriend {
+                user_code: row.get("user_code")?,
+                is_mutual: is_set(row, "is_mutual")?,
+                state: row.get("state")?,
+            })
+        })
+        .and_then(|rows| rows.collect())?;
+
+    let read_backup = |row: &rusqlite::Row| {
+        Ok(ArchivedBackup {
+            version: row.get("version")?,
+            unlocklist: row.get("unlocklist")?,
+            installid: row.get("installid")?,
+            devicemodel_name: row.get("devicemodel_name")?,
+            story: row.get("story")?,
+            create_at: row.get("create_at")?,
+        })
+    };
+    let cloud_save = conn
+        .query_row(sql_stmt::QUERY_BACKUP_DATA, params![user_id], read_backup)
+        .optional()?;
+    let save_history = conn
+        .prepare(sql_stmt::ARCHIVE_BACKUP_HISTORY)?
+        .query_map(params![user_id], read_backup)
+        .and_then(|rows| rows.collect())?;
+
+    Ok(UserArchive {
+        version: ARCHIVE_VERSION,
+        exported_at: chrono::Utc::now().timestamp(),
+        profile,
+        settings,
+        items,
+        purchases,
+        partners,
+        world,
+        scores,
+        recent_scores,
+        friends,
+        cloud_save,
+        save_history,
+    })
+}
+
+pub fn import_user(conn: &mut DBAccessManager, archive: &UserArchive) -> ZrcDBResult<ImportReport> {
+    use rand::{thread_rng, Rng};
+
+    if archive.version > ARCHIVE_VERSION {
+        return Err(ZrcDBError::Other(format!(
+            "archive version {} is newer than supported version {}",
+            archive.version, ARCHIVE_VERSION
+        )));
+    }
+    let map_err = |e| DBAccessManager::map_err("while importing user", Some(e));
+    let profile = &archive.profile;
+    let settings = &archive.settings;
+
+    let tx = conn.connection.transaction().map_err(map_err)?;
+    DBAccessManager::is_user_exists(&tx, &profile.user_name, &profile.email)?;
+
+    let user_id: isize = tx
+        .query_row(sql_stmt::GET_NEW_USER_ID, [], |row| row.get("user_id"))
+        .map_err(map_err)?;
+    let code_taken = tx
+        .query_row(sql_stmt::GET_FRIEND_ID, params![profile.user_code], |row| row.get::<usize, isize>(0))
+        .optional()
+        .map_err(map_err)?
+        .is_some();
+    let user_code = if code_taken {
+        let start: u32 = thread_rng().gen_range(0..=999_999_999);
+        tx.query_row(sql_stmt::GET_NEW_USER_CODE, [start], |row| row.get(0))
+            .map_err(map_err)?
+    } else {
+        profile.user_code
+    };
+
+    tx.execute(
+        sql_stmt::IMPORT_PLAYER,
+        params![
+            user_id,
+            profile.user_name,
+            user_code,
+            profile.display_name,
+            profile.email,
+            profile.pwdhash,
+            profile.ticket,
+            profile.partner,
+            flag(profile.is_locked_name_duplicated),
+            flag(profile.is_skill_sealed),
+            profile.curr_map,
+            profile.prog_boost,
+            profile.stamina,
+            profile.next_fragstam_ts,
+            profile.max_stamina_ts,
+            profile.recent_score_date,
+            profile.max_friend,
+            profile.rating,
+            profile.join_date,
+            flag(settings.max_stamina_notification_enabled),
+            flag(settings.is_hide_rating),
+            settings.favorite_partner,
+            flag(settings.is_friend_approval_required),
+        ],
+    )
+    .map_err(map_err)?;
+
+    for core in &archive.items.cores {
+        tx.execute(sql_stmt::GRANT_CORE, params![user_id, core.core_type, core.amount])
+            .map_err(map_err)?;
+    }
+    if archive.items.fragment != 0 {
+        tx.execute(sql_stmt::GRANT_FRAGMENT, params![user_id, archive.items.fragment])
+            .map_err(map_err)?;
+    }
+    for pack in &archive.purchases.packs {
+        tx.execute(sql_stmt::PURCHASE_PACK, params![user_id, pack])
+            .map_err(map_err)?;
+    }
+    for song in &archive.purchases.singles {
+        tx.execute(sql_stmt::PURCHASE_SINGLE, params![user_id, song])
+            .map_err(map_err)?;
+    }
+    for partner in &archive.partners {
+        tx.execute(
+            sql_stmt::IMPORT_PART_STATS,
+            params![
+                user_id,
+                partner.part_id,
+                flag(partner.is_uncapped_override),
+                flag(partner.is_uncapped),
+                partner.exp_val,
+                partner.overdrive,
+                partner.prog,
+                partner.frag,
+                partner.lv,
+            ],
+        )
+        .map_err(map_err)?;
+    }
+    for map in &archive.world {
+        tx.execute(
+            sql_stmt::IMPORT_MAP_PROGRESS,
+            params![user_id, map.map_id, map.curr_capture, map.curr_position, flag(map.is_locked)],
+        )
+        .map_err(map_err)?;
+    }
+
+    for score in &archive.scores {
+        tx.execute(
+            sql_stmt::IMPORT_SCORE,
+            params![
+                user_id,
+                score.played_date,
+                score.song_id,
+                score.difficulty,
+                score.score,
+                score.shiny_pure,
+                score.pure,
+                score.far,
+                score.lost,
+                score.rating,
+                score.health,
+                score.modifier,
+                score.clear_type,
+            ],
+        )
+        .map_err(map_err)?;
+        if score.is_best {
+            tx.execute(sql_stmt::INSERT_BEST_SCORE, params![user_id, score.played_date])
+                .map_err(map_err)?;
+        }
+    }
+    for recent in &archive.recent_scores {
+        tx.execute(
+            sql_stmt::INSERT_RECENT_SCORE,
+            params![user_id, recent.played_date, flag(recent.is_recent_10)],
+        )
+        .map_err(map_err)?;
+    }
+
+    let mut missing_friends = Vec::new();
+    let mut one_sided_friends = Vec::new();
+    for friend in &archive.friends {
+        let friend_id = tx
+            .query_row(sql_stmt::GET_FRIEND_ID, params![friend.user_code], |row| row.get::<usize, isize>(0))
+            .optional()
+            .map_err(map_err)?;
+        match friend_id {
+            Some(friend_id) if friend_id != user_id => {
+                // only user's own list is restored, user code is only unique
+                // within a server so this may not be the same person, and
+                // their approval setting applies as when adding a friend
+                let approval_required = friend.state.is_empty()
+                    && tx
+                        .query_row(sql_stmt::CHECK_FRIEND_APPROVAL_REQUIRED, params![friend_id], |row| {
+                            row.get::<usize, bool>(0)
+                        })
+                        .map_err(map_err)?;
+                let state = if approval_required { FRIEND_PENDING } else { friend.state.as_str() };
+                tx.execute(sql_stmt::ADD_FRIEND, params![user_id, friend_id, state])
+                    .map_err(map_err)?;
+                if friend.is_mutual && friend.state.is_empty() {
+                    one_sided_friends.push(friend.user_code);
+                }
+            }
+            _ => missing_friends.push(friend.user_code),
+        }
+    }
+
+    let insert_backup = |stmt: &str, backup: &ArchivedBackup| {
+        tx.execute(
+            stmt,
+            params![
+                user_id,
+                backup.version,
+                backup.unlocklist,
+                backup.installid,
+                backup.devicemodel_name,
+                backup.story,
+                backup.create_at,
+            ],
+        )
+        .map_err(map_err)
+    };
+    if let Some(backup) = &archive.cloud_save {
+        insert_backup(sql_stmt::INSERT_OTHER_BACKUP, backup)?;
+    }
+    for backup in &archive.save_history {
+        insert_backup(sql_stmt::INSERT_BACKUP_HISTORY, backup)?;
+    }
+
+    tx.commit().map_err(map_err)?;
+    Ok(ImportReport {
+        user_id,
+        user_code,
+        code_changed: code_taken,
+        missing_friends,
+        one_sided_friends,
+    })
+}
@@ -1,4 +1,5 @@
 use super::*;
+use rusqlite::OptionalExtension;
 
 // ----------------------------------------------------------------------------
 #[derive(Serialize)]
@@ -9,11 +10,30 @@ struct Setting {
 }
 
 #[derive(Serialize)]
-struct CoreInfo {
-    core_type: String,
-    amount: i8,
+pub struct CoreInfo {
+    pub core_type: String,
+    pub amount: isize,
     #[serde(rename = "_id")]
-    id: String,
+    pub id: String,
+}
+
+impl CoreInfo {
+    pub fn new(core_type: String, amount: isize) -> Self {
+        CoreInfo {
+            id: core_type.clone(),
+            core_type,
+            amount,
+        }
+    }
+
+    /// All cores a user currently owns.
+    pub fn get_user_cores(conn: &DBAccessManager, user_id: isize) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn.connection.prepare(sql_stmt::GET_USER_CORES)?;
+        let cores = stmt.query_map(params![user_id], |row| {
+            Ok(CoreInfo::new(row.get("core_type")?, row.get("amount")?))
+        })?;
+        cores.collect()
+    }
 }
 
 #[derive(Serialize)]
@@ -36,34 +56,31 @@ pub struct MostRecentScore {
     best_clear_type: i8,
 }
 
+impl MostRecentScore {
+    fn from_row(row: &rusqlite::Row) -> Result<Self, rusqlite::Error> {
+        Ok(MostRecentScore {
+            song_id: row.get("song_id")?,
+            difficulty: row.get("difficulty")?,
+            score: row.get("score")?,
+            shiny: row.get("shiny_pure")?,
+            pure: row.get("pure")?,
+            far: row.get("far")?,
+            lost: row.get("lost")?,
+            health: row.get("health")?,
+            modifier: row.get("modifier")?,
+            time_played: row.get::<&str, i64>("played_date")? * 1000,
+            clear_type: row.get("clear_type")?,
+            best_clear_type: row.get("best_clear_type")?,
+        })
+    }
+}
+
 fn get_most_recent_score(conn: &DBAccessManager, user_id: isize) -> Result<Option<MostRecentScore>, rusqlite::Error> {
     let mut stmt = conn
         .connection
         .prepare(sql_stmt::USER_MOST_RECENT_SCORE)?;
-    let score = match stmt
-        .query_row(params![user_id], |row| {
-            Ok(MostRecentScore {
-                song_id: row.get("song_id")?,
-                difficulty: row.get("difficulty")?,
-                score: row.get("score")?,
-                shiny: row.get("shiny_pure")?,
-                pure: row.get("pure")?,
-                far: row.get("far")?,
-                lost: row.get("lost")?,
-                health: row.get("health")?,
-                modifier: row.get("modifier")?,
-                time_played: row.get::<&str, i64>("played_date")? * 1000,
-                clear_type: row.get("clear_type")?,
-                best_clear_type: row.get("best_clear_type")?,
-            })
-        }) {
-            Ok(s) => s,
-            Err(e) => match e {
-                rusqlite::Error::QueryReturnedNoRows => return Ok(None),
-                _ => return Err(e)
-            }
-        };
-    Ok(Some(score))
+    stmt.query_row(params![user_id], MostRecentScore::from_row)
+        .optional()
 }
 
 #[derive(Serialize)]
@@ -78,6 +95,7 @@ pub struct UserInfo {
     display_name: String,
     user_code: String,
     ticket: isize,
+    fragment: isize,
     character: i8,
     is_locked_name_duplicate: bool,
     is_skill_sealed: bool,
@@ -93,7 +111,7 @@ pub struct UserInfo {
     characters: Vec<i8>,
     cores: Vec<CoreInfo>,
     recent_score: Vec<MostRecentScore>,
-    max_friend: i8,
+    max_friend: isize,
     rating: isize,
     join_date: i64,
 }
@@ -105,6 +123,7 @@ impl UserInfo {
         let world_songs = get_item_list(conn, "item_name", "world_song_unlock", user_id)?;
         let packs = get_item_list(conn, "pack_name", "pack_purchase_info", user_id)?;
         let singles = get_item_list(conn, "song_id", "single_purchase_info", user_id)?;
+        let cores = CoreInfo::get_user_cores(conn, user_id)?;
         let mut user_info = stmt
             .query_row(params![user_id], |row| {
                 let settings = Setting {
@@ -127,6 +146,7 @@ impl UserInfo {
                     display_name: row.get("display_name")?,
                     user_code: format!("{:0>9}", row.get::<&str, i64>("user_code")?),
                     ticket: row.get("ticket")?,
+                    fragment: row.get("fragment")?,
                     character: row.get("partner")?,
                     is_locked_name_duplicate: row.get::<&str, String>("locked")? == "t",
                     is_skill_sealed: row.get::<&str, String>("skill_sealed")? == "t",
@@ -140,7 +160,7 @@ impl UserInfo {
                     singles,
                     packs,
                     characters,
-                    cores: Vec::new(),
+                    cores,
                     recent_score: Vec::new(),
                     max_friend: row.get("max_friend")?,
                     rating: row.get("rating")?,
@@ -181,31 +201,62 @@ impl UserInfoMinimum {
         let mut stmt = conn
             .connection
             .prepare(sql_stmt::MINIMUM_USER_INFO)?;
-        let mut user_info = stmt
-            .query_row(params![user_id], |row| {
-                Ok(UserInfoMinimum {
-                    name: row.get("user_name")?,
-                    user_id,
-                    user_code: row.get::<&str, isize>("user_code")?,
-                    character: row.get("partner")?,
-                    favorite_character: row.get("fav_partner")?,
-                    is_skill_sealed: row.get::<&str, String>("sealed")? == "t",
-                    is_uncapped: row.get::<&str, String>("uncapped")? == "t",
-                    is_uncapped_override: row.get::<&str, String>("uncapped_override")? == "t",
-                    rating: row.get("rating")?,
-                    is_hide_rating: row.get::<&str, String>("hide_rating")? == "t",
-                    recent_score: Vec::new(),
-                    join_date: row.get("join_date")?,
-                    is_mutual: false,
-                })
-            })?;
-        match get_most_recent_score(conn, user_id)? {
-            None => {},
-            Some(score) => user_info.recent_score.push(score),
-        };
+        let mut user_info = stmt.query_row(params![user_id], |row| Self::from_row(row, user_id))?;
+        if let Some(score) = get_most_recent_score(conn, user_id)? {
+            user_info.recent_score.push(score);
+        }
         Ok(user_info)
     }
 
+    /// Minimum info of all friends of a user, with mutual state and most
+    /// recent score filled in. Loaded with two queries regardless of number
+    /// of friends.
+    pub fn get_friends(conn: &DBAccessManager, user_id: isize) -> Result<Vec<Self>, rusqlite::Error> {
+        let mut stmt = conn
+            .connection
+            .prepare(sql_stmt::FRIEND_MINIMUM_USER_INFO)?;
+        let mut friends = stmt
+            .query_map(params![user_id], |row| {
+                let mut info = Self::from_row(row, row.get("user_id")?)?;
+                info.is_mutual = row.get::<&str, String>("is_mutual")? == "t";
+                Ok(info)
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())?;
+
+        let mut stmt = conn
+            .connection
+            .prepare(sql_stmt::FRIEND_MOST_RECENT_SCORE)?;
+        let mut scores = stmt
+            .query_map(params![user_id], |row| {
+                Ok((row.get::<&str, isize>("user_id")?, MostRecentScore::from_row(row)?))
+            })
+            .and_then(|rows| rows.collect::<Result<HashMap<_, _>, _>>())?;
+        for info in friends.iter_mut() {
+            if let Some(score) = scores.remove(&info.user_id) {
+                info.recent_score.push(score);
+            }
+        }
+        Ok(friends)
+    }
+
+    fn from_row(row: &rusqlite::Row, user_id: isize) -> Result<Self, rusqlite::Error> {
+        Ok(UserInfoMinimum {
+            name: row.get("user_name")?,
+            user_id,
+            user_code: row.get::<&str, isize>("user_code")?,
+            character: row.get("partner")?,
+            favorite_character: row.get("fav_partner")?,
+            is_skill_sealed: row.get::<&str, String>("sealed")? == "t",
+            is_uncapped: row.get::<&str, String>("uncapped")? == "t",
+            is_uncapped_override: row.get::<&str, String>("uncapped_override")? == "t",
+            rating: row.get("rating")?,
+            is_hide_rating: row.get::<&str, String>("hide_rating")? == "t",
+            recent_score: Vec::new(),
+            join_date: row.get("join_date")?,
+            is_mutual: false,
+        })
+    }
+
     pub fn get_rating_level(&self) -> i8 {
         lazy_static! {
             static ref RATING_LEVEL_STEP: [isize; 6] = [349, 699, 999, 1000, 1199, 1249,];
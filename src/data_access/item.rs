@@ -0,0 +1,187 @@
+use super::*;
+use std::str::FromStr;
+
+/// Kind of item that can be handed to player by redemption code, present and
+/// the like.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    Pack,
+    Single,
+    Character,
+    Core,
+    Ticket,
+    Fragment,
+    /// Raises capacity of friend list.
+    FriendSlot,
+}
+
+impl GrantType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GrantType::Pack => "pack",
+            GrantType::Single => "single",
+            GrantType::Character => "character",
+            GrantType::Core => "core",
+            GrantType::Ticket => "ticket",
+            GrantType::Fragment => "fragment",
+            GrantType::FriendSlot => "friend_slot",
+        }
+    }
+}
+
+impl FromStr for GrantType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pack" => Ok(GrantType::Pack),
+            "single" => Ok(GrantType::Single),
+            "character" => Ok(GrantType::Character),
+            "core" => Ok(GrantType::Core),
+            "ticket" => Ok(GrantType::Ticket),
+            "fragment" => Ok(GrantType::Fragment),
+            "friend_slot" => Ok(GrantType::FriendSlot),
+            _ => Err(format!("unknown item type '{}'", s)),
+        }
+    }
+}
+
+/// A single item given to player. `id` is pack name, song id, partner id or
+/// core type depending on `item_type`, and is empty for ticket, fragment and
+/// friend slot.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ItemGrant {
+    #[serde(rename = "type")]
+    pub item_type: GrantType,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub amount: i32,
+}
+
+impl FromStr for ItemGrant {
+    type Err = String;
+
+    /// Parse item in form of `type:id:amount`, e.g. `core:core_generic:5`,
+    /// `ticket::500` or `pack:base`. Amount defaults to 1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let item_type = parts.next().unwrap_or_default().parse::<GrantType>()?;
+        let id = parts.next().unwrap_or_default().to_string();
+        let amount = match parts.next() {
+            Some(a) if !a.is_empty() => a
+                .parse::<i32>()
+                .map_err(|e| format!("invalid amount '{}', {}", a, e))?,
+            _ => 1,
+        };
+        if amount <= 0 {
+            return Err(format!("amount must be positive, got {}", amount));
+        }
+        let needs_id = !matches!(
+            item_type,
+            GrantType::Ticket | GrantType::Fragment | GrantType::FriendSlot
+        );
+        if needs_id && id.is_empty() {
+            return Err(format!("item of type '{}' needs an id", item_type.as_str()));
+        }
+        Ok(ItemGrant {
+            item_type,
+            id,
+            amount,
+        })
+    }
+}
+
+impl ItemGrant {
+    /// Give this item to user within given transaction. Packs, singles and
+    /// partners already owned by user are left as is. Items with non-positive
+    /// amount are refused, so they can't take anything away from user.
+    pub fn grant(&self, tx: &rusqlite::Transaction, user_id: isize) -> ZrcDBResult<()> {
+        if self.amount <= 0 {
+            return Err(ZrcDBError::Other(format!(
+                "amount of {} '{}' must be positive, got {}",
+                self.item_type.as_str(),
+                self.id,
+                self.amount
+            )));
+        }
+        if !self.is_available(tx)? {
+            return Err(ZrcDBError::ItemNotAvailable(format!(
+                "{} '{}'",
+                self.item_type.as_str(),
+                self.id
+            )));
+        }
+        let result = match self.item_type {
+            GrantType::Pack => tx.execute(sql_stmt::GRANT_PACK, params![user_id, self.id]),
+            GrantType::Single => tx.execute(sql_stmt::GRANT_SINGLE, params![user_id, self.id]),
+            GrantType::Character => tx.execute(sql_stmt::GRANT_CHARACTER, params![user_id, self.id]),
+            GrantType::Core => tx.execute(sql_stmt::GRANT_CORE, params![user_id, self.id, self.amount]),
+            GrantType::Ticket => tx.execute(sql_stmt::GRANT_TICKET, params![self.amount, user_id]),
+            GrantType::Fragment => tx.execute(sql_stmt::GRANT_FRAGMENT, params![user_id, self.amount]),
+            GrantType::FriendSlot => tx.execute(sql_stmt::GRANT_FRIEND_SLOT, params![self.amount, user_id]),
+        };
+        result.map_err(|e| {
+            DBAccessManager::map_err(
+                &format!(
+                    "while granting {} '{}' to user '{}'",
+                    self.item_type.as_str(),
+                    self.id,
+                    user_id
+                ),
+                Some(e),
+            )
+        })?;
+        Ok(())
+    }
+
+    // Check whether pack, single or partner this item refers to exists.
+    fn is_available(&self, tx: &rusqlite::Transaction) -> ZrcDBResult<bool> {
+        let stmt = match self.item_type {
+            GrantType::Pack => sql_stmt::CHECK_PACK_EXISTS,
+            GrantType::Single => sql_stmt::CHECK_SINGLE_EXISTS,
+            GrantType::Character => sql_stmt::CHECK_PARTNER_EXISTS,
+            GrantType::Core | GrantType::Ticket | GrantType::Fragment | GrantType::FriendSlot => {
+                return Ok(true)
+            }
+        };
+        tx.query_row(stmt, params![self.id], |row| row.get::<usize, bool>(0))
+            .map_err(|e| DBAccessManager::map_err("while checking item existance", Some(e)))
+    }
+}
+
+/// Read item list of a redemption code, present and the like with given
+/// statement, which takes key as its only parameter and returns `item_type`,
+/// `item_id` and `amount` columns.
+pub fn query_items(
+    conn: &rusqlite::Connection,
+    stmt: &str,
+    key: &str,
+) -> ZrcDBResult<Vec<ItemGrant>> {
+    let mut stmt = conn.prepare(stmt).map_err(|e| {
+        DBAccessManager::map_err("while preparing statement for item list", Some(e))
+    })?;
+    let rows = stmt
+        .query_map(params![key], |row| {
+            Ok((
+                row.get::<&str, String>("item_type")?,
+                row.get::<&str, String>("item_id")?,
+                row.get::<&str, i32>("amount")?,
+            ))
+        })
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            DBAccessManager::map_err(&format!("while querying item list of '{}'", key), Some(e))
+        })?;
+    let mut items = Vec::new();
+    for (item_type, id, amount) in rows {
+        let item_type = item_type.parse::<GrantType>().map_err(ZrcDBError::Other)?;
+        items.push(ItemGrant {
+            item_type,
+            id,
+            amount,
+        });
+    }
+    Ok(items)
+}
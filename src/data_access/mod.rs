@@ -1,8 +1,12 @@
 use thiserror::Error;
 
+pub mod archive;
 mod info;
+mod item;
+mod present;
 pub mod save;
 mod score;
+mod song;
 mod sql_stmt;
 
 mod dlc {
@@ -84,28 +88,14 @@ mod dlc {
     }
 
     impl DLItem {
-        pub fn song_dl_url(
-            &self,
-            hostname: &str,
-            prefix_static_file: &str,
-            songs_dirname: &str,
-        ) -> String {
-            format!(
-                "http://{}/{}/{}/{}/{}",
-                hostname, prefix_static_file, songs_dirname, self.song_id, "base.ogg"
-            )
+        /// Path of audio file relative to static file directory.
+        pub fn song_file_path(&self, songs_dirname: &str) -> String {
+            format!("{}/{}/{}", songs_dirname, self.song_id, "base.ogg")
         }
 
-        pub fn chart_dl_url(
-            &self,
-            hostname: &str,
-            prefix_static_file: &str,
-            songs_dirname: &str,
-        ) -> String {
-            format!(
-                "http://{}/{}/{}/{}/{}.aff",
-                hostname, prefix_static_file, songs_dirname, self.song_id, self.difficulty
-            )
+        /// Path of chart file relative to static file directory.
+        pub fn chart_file_path(&self, songs_dirname: &str) -> String {
+            format!("{}/{}/{}.aff", songs_dirname, self.song_id, self.difficulty)
         }
     }
 
@@ -145,8 +135,22 @@ mod dlc {
 
 mod character {
     use super::*;
-
-    const VOICE: [isize; 7] = [0, 1, 2, 3, 100, 1000, 1001];
+    use rusqlite::OptionalExtension;
+
+    /// Partners every user has from the beginning, Hikari and Tairitsu. Others
+    /// are unlocked with packs, presents and serial codes. World map rewards
+    /// don't unlock partners, as the server doesn't track world progression
+    /// and never hands out `map_reward` items.
+    pub const INITIAL_PARTNERS: [isize; 2] = [0, 1];
+    /// Experience every play gives to active partner, regardless of rating.
+    const PLAY_EXP_BASE: f64 = 100.;
+    /// Extra experience for each point of play rating.
+    const PLAY_EXP_PER_RATING: f64 = 50.;
+    /// Partner stats grow linearly from level 1 to this level, and stay at
+    /// their `*_20` value beyond it.
+    const STATS_GROWTH_LEVEL: i8 = 20;
+    const MAX_LEVEL: i8 = 20;
+    const MAX_LEVEL_UNCAPPED: i8 = 30;
 
     #[derive(Serialize)]
     struct CharacterStats {
@@ -154,7 +158,7 @@ mod character {
         voice: Vec<isize>,
         is_uncapped_override: bool,
         is_uncapped: bool,
-        uncap_cores: Vec<String>,
+        uncap_cores: Vec<CoreInfo>,
         char_type: i8,
         skill_id_uncap: Option<String>,
         skill_requires_uncap: bool,
@@ -189,51 +193,221 @@ mod character {
                 conn.connection
                     .prepare(&format!("{}{};", sql_stmt::CHAR_STATS, cond))?;
 
-            // TODO: Possible error point
             let statses = stmt.query_map(params![user_id], |row| {
+                let level: i8 = row.get("lv")?;
+                let skill_unlock_level: i8 = row.get("skill_unlock_level")?;
                 Ok(CharacterStats {
-                    voice: if row.get::<&str, isize>("have_voice")? >= 0 {
-                        VOICE.iter().map(|x| *x).collect()
-                    } else {
-                        Vec::new()
-                    },
+                    voice: Vec::new(),
                     is_uncapped_override: row.get::<&str, String>("uncapped_override")? == "t",
                     is_uncapped: row.get::<&str, String>("uncapped")? == "t",
                     uncap_cores: Vec::new(),
                     char_type: row.get("char_type")?,
                     skill_id_uncap: row.get("uncap_skill")?,
                     skill_requires_uncap: row.get::<&str, String>("skill_requires_uncap")? == "t",
-                    skill_unlock_level: row.get("skill_unlock_level")?,
-                    skill_id: row.get("skill_id")?,
+                    skill_unlock_level,
+                    skill_id: if level >= skill_unlock_level {
+                        row.get("skill_id")?
+                    } else {
+                        None
+                    },
                     overdrive: row.get("overdrive")?,
                     prog: row.get("prog")?,
                     frag: row.get("frag")?,
                     level_exp: row.get("level_exp")?,
                     exp: row.get("exp_val")?,
-                    level: row.get("lv")?,
+                    level,
                     name: row.get("part_name")?,
                     character_id: row.get("part_id")?,
                     prog_tempest: row.get("prog_tempest")?,
                 })
             })?;
-            Ok(CharacterStatses(
-                statses.into_iter().map(|s| s.unwrap()).collect(),
-            ))
+            let mut statses = statses.collect::<Result<Vec<CharacterStats>, _>>()?;
+
+            let mut voices = get_voices(conn)?;
+            let mut uncap_cores = get_uncap_cores(conn)?;
+            for stats in statses.iter_mut() {
+                if let Some(voice) = voices.remove(&stats.character_id) {
+                    stats.voice = voice;
+                }
+                if let Some(cores) = uncap_cores.remove(&stats.character_id) {
+                    stats.uncap_cores = cores;
+                }
+            }
+            Ok(CharacterStatses(statses))
         }
 
         pub fn list_char_ids(&self) -> Vec<i8> {
             self.0.iter().map(|x| x.character_id).collect()
         }
     }
+
+    // Voice list of each partner, keyed by partner id.
+    fn get_voices(conn: &DBAccessManager) -> Result<HashMap<i8, Vec<isize>>, rusqlite::Error> {
+        let mut stmt = conn.connection.prepare(sql_stmt::QUERY_PARTNER_VOICE)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<&str, i8>("part_id")?,
+                row.get::<&str, isize>("voice_id")?,
+            ))
+        })?;
+        let mut voices: HashMap<i8, Vec<isize>> = HashMap::new();
+        for row in rows {
+            let (part_id, voice_id) = row?;
+            voices.entry(part_id).or_default().push(voice_id);
+        }
+        Ok(voices)
+    }
+
+    // Cores needed for uncapping each partner, keyed by partner id.
+    fn get_uncap_cores(
+        conn: &DBAccessManager,
+    ) -> Result<HashMap<i8, Vec<CoreInfo>>, rusqlite::Error> {
+        let mut stmt = conn.connection.prepare(sql_stmt::QUERY_UNCAP_CORES)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<&str, i8>("part_id")?,
+                CoreInfo::new(row.get("core_type")?, row.get("amount")?),
+            ))
+        })?;
+        let mut cores: HashMap<i8, Vec<CoreInfo>> = HashMap::new();
+        for row in rows {
+            let (part_id, core) = row?;
+            cores.entry(part_id).or_default().push(core);
+        }
+        Ok(cores)
+    }
+
+    /// Uncap a partner for user, consuming cores required by this partner.
+    pub fn uncap_character(
+        tx: &rusqlite::Transaction,
+        user_id: isize,
+        part_id: isize,
+    ) -> ZrcDBResult<()> {
+        let is_uncapped = tx
+            .query_row(sql_stmt::QUERY_IS_UNCAPPED, params![user_id, part_id], |row| {
+                Ok(row.get::<usize, String>(0)? == "t")
+            })
+            .map_err(|e| {
+                DBAccessManager::map_err(
+                    &format!("while checking partner '{}' of user '{}'", part_id, user_id),
+                    Some(e),
+                )
+            })?;
+        if is_uncapped {
+            return Err(ZrcDBError::AlreadyUncapped);
+        }
+
+        let mut stmt = tx.prepare(sql_stmt::QUERY_PART_UNCAP_CORES).map_err(|e| {
+            DBAccessManager::map_err("while preparing statement for uncap cores", Some(e))
+        })?;
+        let requirements = stmt
+            .query_map(params![user_id, part_id], |row| {
+                Ok((
+                    row.get::<&str, String>("core_type")?,
+                    row.get::<&str, isize>("required")?,
+                    row.get::<&str, isize>("owned")?,
+                ))
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| DBAccessManager::map_err("while querying uncap cores", Some(e)))?;
+        if requirements.is_empty() {
+            return Err(ZrcDBError::CannotUncap);
+        }
+
+        for (core_type, required, owned) in requirements {
+            if owned < required {
+                return Err(ZrcDBError::NotEnoughCore(core_type));
+            }
+            tx.execute(sql_stmt::CONSUME_CORE, params![required, user_id, core_type])
+                .map_err(|e| DBAccessManager::map_err("while consuming cores", Some(e)))?;
+        }
+        tx.execute(sql_stmt::UNCAP_CHARACTER, params![user_id, part_id])
+            .map_err(|e| DBAccessManager::map_err("while uncapping partner", Some(e)))?;
+        Ok(())
+    }
+
+    /// Experience active partner gains from a play with given rating.
+    pub fn play_exp(rating: f64) -> f64 {
+        PLAY_EXP_BASE + rating.max(0.) * PLAY_EXP_PER_RATING
+    }
+
+    // Value of a partner stat at given level, interpolated between its level 1
+    // and level 20 value.
+    fn stat_at_level(value_1: f64, value_20: f64, level: i8) -> f64 {
+        let level = level.clamp(1, STATS_GROWTH_LEVEL);
+        value_1
+            + (value_20 - value_1) * f64::from(level - 1) / f64::from(STATS_GROWTH_LEVEL - 1)
+    }
+
+    /// Add experience to user's current partner, level it up according to
+    /// `level_exp` table and rescale its stats for the new level.
+    pub fn gain_partner_exp(
+        tx: &rusqlite::Transaction,
+        user_id: isize,
+        exp_gain: f64,
+    ) -> Result<(), rusqlite::Error> {
+        let partner = tx
+            .query_row(sql_stmt::QUERY_PARTNER_GROWTH, params![user_id], |row| {
+                Ok((
+                    row.get::<&str, isize>("part_id")?,
+                    row.get::<&str, f64>("exp_val")?,
+                    row.get::<&str, String>("uncapped")? == "t",
+                    (row.get::<&str, f64>("frag_1")?, row.get::<&str, f64>("frag_20")?),
+                    (row.get::<&str, f64>("prog_1")?, row.get::<&str, f64>("prog_20")?),
+                    (
+                        row.get::<&str, f64>("overdrive_1")?,
+                        row.get::<&str, f64>("overdrive_20")?,
+                    ),
+                ))
+            })
+            .optional()?;
+        let (part_id, exp, is_uncapped, frag, prog, overdrive) = match partner {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let max_level = if is_uncapped {
+            MAX_LEVEL_UNCAPPED
+        } else {
+            MAX_LEVEL
+        };
+        let max_exp: f64 = tx.query_row(sql_stmt::QUERY_LEVEL_EXP, params![max_level], |row| {
+            row.get(0)
+        })?;
+        let exp = (exp + exp_gain).min(max_exp);
+        let level: i8 = tx.query_row(
+            sql_stmt::QUERY_LEVEL_FOR_EXP,
+            params![exp, max_level],
+            |row| row.get(0),
+        )?;
+
+        tx.execute(
+            sql_stmt::UPDATE_PARTNER_GROWTH,
+            params![
+                exp,
+                level,
+                stat_at_level(frag.0, frag.1, level),
+                stat_at_level(prog.0, prog.1, level),
+                stat_at_level(overdrive.0, overdrive.1, level),
+                user_id,
+                part_id,
+            ],
+        )?;
+        Ok(())
+    }
 }
 
 use super::*;
+pub use archive::{ImportReport, UserArchive};
 pub use character::CharacterStatses;
 use dlc::{DLItem, DlcInfo, DlcInfoList, InfoItem};
 pub use dlc::{DLRequest, ItemType};
-pub use info::UserInfoMinimum;
+pub use info::{CoreInfo, UserInfoMinimum};
+pub use item::{GrantType, ItemGrant};
+pub use present::{Present, PresentFilter};
 use info::{GameInfo, MapInfoList, PackInfo, PackItem, UserInfo, UserInfoForItemPurchase};
-pub use score::{LookupedScore, ScoreRecord};
+pub use score::{ComparedScore, LookupedScore, ScoreRecord};
+pub use song::{ChartEntry, SongEntry, SongShelf};
 
 pub type SqlitePool = Arc<Pool<SqliteConnectionManager>>;
 pub type PooledSqlite = PooledConnection<SqliteConnectionManager>;
@@ -254,6 +428,44 @@ pub enum ZrcDBError {
     FriendExists,
     #[error("your can't added yourself as friend")]
     SelfFriend,
+    #[error("your friend list is full")]
+    FriendListFull,
+    #[error("you can't add this user as friend")]
+    FriendBlocked,
+    #[error("no such friend request")]
+    NoFriendRequest,
+    #[error("this user is not your friend")]
+    NotFriend,
+    #[error("you already have this item")]
+    ItemAlreadyAcquired,
+    #[error("{0} is not available")]
+    ItemNotAvailable(String),
+    #[error("not enough ticket for this purchase")]
+    NotEnoughTicket,
+    #[error("invalid serial number")]
+    InvalidSerialNumber,
+    #[error("this serial number is already used")]
+    SerialNumberUsed,
+    #[error("you don't have partner '{0}'")]
+    CharacterNotOwned(isize),
+    #[error("this partner is already uncapped")]
+    AlreadyUncapped,
+    #[error("this partner can't be uncapped")]
+    CannotUncap,
+    #[error("not enough core '{0}' for uncapping")]
+    NotEnoughCore(String),
+    #[error("download limit of {0} songs per 24 hours reached")]
+    DownloadLimitReached(usize),
+    #[error("song '{0}' already exists")]
+    SongExists(String),
+    #[error("no backup with id {0}")]
+    NoSuchBackup(isize),
+    #[error("corrupted backup, {0}")]
+    CorruptedBackup(String),
+    #[error("corrupted backup data on cloud, {0}")]
+    CorruptedCloudData(String),
+    #[error("no backup data on cloud")]
+    NoCloudData,
 }
 
 impl warp::reject::Reject for ZrcDBError {}
@@ -278,10 +490,57 @@ pub struct DBAccessManager {
 }
 
 impl DBAccessManager {
-    pub fn new(connection: PooledSqlite) -> DBAccessManager {
+    pub fn new(mut connection: PooledSqlite) -> DBAccessManager {
+        connection.profile(Some(metrics::observe_query));
         DBAccessManager { connection }
     }
 
+    /// Create tables listed in `sql_stmt::TABLE_SCHEMAS` if they don't exist yet,
+    /// add columns in `sql_stmt::ADDED_COLUMNS` to existing tables, then apply
+    /// `sql_stmt::DATA_MIGRATIONS` not applied before.
+    pub fn init_tables(&self) -> ZrcDBResult<()> {
+        for schema in sql_stmt::TABLE_SCHEMAS {
+            self.connection
+                .execute_batch(schema)
+                .map_err(|e| DBAccessManager::map_err("while creating tables", Some(e)))?;
+        }
+        for (table, column, definition) in sql_stmt::ADDED_COLUMNS {
+            let exists = self
+                .connection
+                .query_row(sql_stmt::CHECK_COLUMN_EXISTS, params![table, column], |row| {
+                    row.get::<usize, bool>(0)
+                })
+                .map_err(|e| DBAccessManager::map_err("while checking table columns", Some(e)))?;
+            if !exists {
+                self.connection
+                    .execute_batch(&format!("alter table {} add column {} {}", table, column, definition))
+                    .map_err(|e| {
+                        DBAccessManager::map_err(
+                            &format!("while adding column '{}' to '{}'", column, table),
+                            Some(e),
+                        )
+                    })?;
+            }
+        }
+        for (name, migration) in sql_stmt::DATA_MIGRATIONS {
+            let map_err = |e| DBAccessManager::map_err(&format!("while applying data migration '{}'", name), Some(e));
+            let applied = self
+                .connection
+                .query_row(sql_stmt::CHECK_DATA_MIGRATION_APPLIED, params![name], |row| row.get::<usize, bool>(0))
+                .map_err(map_err)?;
+            if applied {
+                continue;
+            }
+            let tx = self.connection.unchecked_transaction().map_err(map_err)?;
+            tx.execute_batch(migration).map_err(map_err)?;
+            tx.execute(sql_stmt::INSERT_DATA_MIGRATION, params![name, chrono::Utc::now().timestamp()])
+                .map_err(map_err)?;
+            tx.commit().map_err(map_err)?;
+            log::info!("applied data migration '{}'", name);
+        }
+        Ok(())
+    }
+
     pub fn map_err(msg: &str, err: Option<rusqlite::Error>) -> ZrcDBError {
         let msg = msg.to_string();
         match err {
@@ -302,13 +561,14 @@ impl DBAccessManager {
     /// If song id list contained in `DLRequest` is not empty, returned result
     /// will be info for song in list, other wise, all DLC info will be contained
     /// in returned result.
+    /// `make_url` turns path of a file relative to static file directory into
+    /// its download URL.
     pub fn get_purchase_dl(
         &self,
         user_id: isize,
         requests: DLRequest,
-        hostname: &str,
-        prefix_static_file: &str,
         songs_dirname: &str,
+        make_url: &dyn Fn(&str) -> String,
     ) -> ZrcDBResult<dlc::DlcInfoList> {
         let mut infoes = HashMap::new();
         let song_id_condition = if !requests.song_ids.is_empty() {
@@ -335,9 +595,8 @@ impl DBAccessManager {
             table_name,
             condition,
             &song_id_condition,
-            hostname,
-            prefix_static_file,
             songs_dirname,
+            make_url,
         )
         .map_err(|e| {
             DBAccessManager::map_err(
@@ -359,9 +618,8 @@ impl DBAccessManager {
             table_name,
             condition,
             &song_id_condition,
-            hostname,
-            prefix_static_file,
             songs_dirname,
+            make_url,
         )
         .map_err(|e| {
             DBAccessManager::map_err(
@@ -376,9 +634,50 @@ impl DBAccessManager {
         Ok(infoes)
     }
 
+    /// Check if user has purchased the pack containing given song, or the song
+    /// itself as a single.
+    pub fn is_song_owned(&self, user_id: isize, song_id: &str) -> ZrcDBResult<bool> {
+        self.connection
+            .query_row(sql_stmt::CHECK_SONG_OWNED, params![user_id, song_id], |row| {
+                row.get::<usize, bool>(0)
+            })
+            .map_err(|e| DBAccessManager::map_err("while checking song possession", Some(e)))
+    }
+
+    /// Stored checksum of a file under song's directory, `base.ogg` or
+    /// `<difficulty>.aff`. Returns `None` for other files or missing records.
+    pub fn get_song_file_checksum(
+        &self,
+        song_id: &str,
+        file_name: &str,
+    ) -> ZrcDBResult<Option<String>> {
+        use rusqlite::OptionalExtension;
+
+        let result = if file_name == "base.ogg" {
+            self.connection
+                .query_row(sql_stmt::QUERY_AUDIO_CHECKSUM, params![song_id], |row| {
+                    row.get::<usize, Option<String>>(0)
+                })
+                .optional()
+        } else if let Some(Ok(difficulty)) = file_name.strip_suffix(".aff").map(str::parse::<i8>) {
+            self.connection
+                .query_row(
+                    sql_stmt::QUERY_CHART_CHECKSUM,
+                    params![song_id, difficulty],
+                    |row| row.get::<usize, Option<String>>(0),
+                )
+                .optional()
+        } else {
+            Ok(None)
+        };
+        result
+            .map(Option::flatten)
+            .map_err(|e| DBAccessManager::map_err("while querying file checksum", Some(e)))
+    }
+
     /// Return all DLC info (checksum only).
     pub fn get_all_purchase_dl(&self, user_id: isize) -> ZrcDBResult<dlc::DlcInfoList> {
-        self.get_purchase_dl(user_id, DLRequest::empty_request(), "", "", "")
+        self.get_purchase_dl(user_id, DLRequest::empty_request(), "", &|_| String::new())
     }
 
     // Look up checksum and download URL for DLC with given table name and condition.
@@ -391,9 +690,8 @@ impl DBAccessManager {
         table_name: &str,
         condition: &str,
         song_id_condition: &str,
-        hostname: &str,
-        prefix_static_file: &str,
         songs_dirname: &str,
+        make_url: &dyn Fn(&str) -> String,
     ) -> Result<(), rusqlite::Error> {
         let items = self.get_dl_items(user_id, stmt, table_name, condition, song_id_condition)?;
         for item in items.into_iter().filter(|i| i.chart_dl || i.song_dl) {
@@ -404,7 +702,7 @@ impl DBAccessManager {
             if item.song_dl && !item.audio_checksum.is_empty() {
                 info.audio.checksum = item.audio_checksum.clone();
                 if need_url {
-                    info.audio.url = item.song_dl_url(hostname, prefix_static_file, songs_dirname);
+                    info.audio.url = make_url(&item.song_file_path(songs_dirname));
                 }
             }
             if item.chart_dl && !item.chart_checksum.is_empty() {
@@ -414,7 +712,7 @@ impl DBAccessManager {
                     .or_insert(InfoItem::new());
                 entry.checksum = item.chart_checksum.clone();
                 if need_url {
-                    entry.url = item.chart_dl_url(hostname, prefix_static_file, songs_dirname);
+                    entry.url = make_url(&item.chart_file_path(songs_dirname));
                 }
             }
         }
@@ -456,16 +754,50 @@ impl DBAccessManager {
         item_id: &str,
         item_type: ItemType,
     ) -> ZrcDBResult<UserInfoForItemPurchase> {
+        use rusqlite::OptionalExtension;
+        use std::time::SystemTime;
+
         let tx = self.connection.transaction().map_err(|e| {
             DBAccessManager::map_err("while opening transacation for pack purchasing", Some(e))
         })?;
         {
-            let stmt = match item_type {
-                ItemType::Pack => sql_stmt::PURCHASE_PACK,
-                ItemType::Single => sql_stmt::PURCHASE_SINGLE,
+            let (check_stmt, price_stmt, stmt) = match item_type {
+                ItemType::Pack => (
+                    sql_stmt::CHECK_PACK_OWNED,
+                    sql_stmt::QUERY_PACK_PRICE,
+                    sql_stmt::PURCHASE_PACK,
+                ),
+                ItemType::Single => (
+                    sql_stmt::CHECK_SINGLE_OWNED,
+                    sql_stmt::QUERY_SINGLE_PRICE,
+                    sql_stmt::PURCHASE_SINGLE,
+                ),
             };
-            // TODO: check user possession, prevent repeatedly purchase
-            // TODO: modify user ticket
+            let is_owned = tx
+                .query_row(check_stmt, params![user_id, item_id], |row| row.get::<usize, bool>(0))
+                .map_err(|e| {
+                    DBAccessManager::map_err("while checking possession of item", Some(e))
+                })?;
+            if is_owned {
+                return Err(ZrcDBError::ItemAlreadyAcquired);
+            }
+
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64;
+            let price: isize = tx
+                .query_row(price_stmt, params![item_id, now], |row| row.get(0))
+                .optional()
+                .map_err(|e| DBAccessManager::map_err("while querying item price", Some(e)))?
+                .ok_or_else(|| ZrcDBError::ItemNotAvailable(format!("item '{}'", item_id)))?;
+            let updated = tx
+                .execute(sql_stmt::CONSUME_TICKET, params![price, user_id])
+                .map_err(|e| DBAccessManager::map_err("while consuming ticket", Some(e)))?;
+            if updated == 0 {
+                return Err(ZrcDBError::NotEnoughTicket);
+            }
+
             let mut stmt = tx.prepare(stmt).map_err(|e| {
                 DBAccessManager::map_err("while preparing statement for pack purchasing", Some(e))
             })?;
@@ -476,32 +808,343 @@ impl DBAccessManager {
                 )
             })?;
         }
+        if let ItemType::Pack = item_type {
+            DBAccessManager::grant_pack_items(&tx, user_id, item_id)?;
+        }
         tx.commit()
             .map_err(|e| DBAccessManager::map_err("while commit purchase", Some(e)))?;
         UserInfoForItemPurchase::new(self, user_id).map_err(|e| {
             DBAccessManager::map_err("while generate user info after pack purchasing", Some(e))
         })
     }
+
+    // Grant partners and friend slots that come with a pack.
+    fn grant_pack_items(
+        tx: &rusqlite::Transaction,
+        user_id: isize,
+        pack_name: &str,
+    ) -> ZrcDBResult<()> {
+        for item in item::query_items(tx, sql_stmt::PACK_BONUS_ITEM, pack_name)? {
+            item.grant(tx, user_id)?;
+        }
+        Ok(())
+    }
+
+    /// Count downloads of given songs against user's quota of the last 24 hours.
+    /// Each song is counted and recorded once per window, so fetching the same
+    /// song or its other files again takes no more quota. Nothing is recorded
+    /// if quota would be exceeded. `daily_limit` of 0 means no limit.
+    pub fn record_downloads(
+        &mut self,
+        user_id: isize,
+        song_ids: &[&String],
+        daily_limit: usize,
+    ) -> ZrcDBResult<()> {
+        use std::collections::HashSet;
+        use std::time::SystemTime;
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transaction for download recording", Some(e))
+        })?;
+        let recent_songs = tx
+            .prepare(sql_stmt::RECENT_DOWNLOAD_SONGS)
+            .and_then(|mut stmt| {
+                stmt.query_map(params![user_id, now - 24 * 3600 * 1000], |row| {
+                    row.get::<usize, String>(0)
+                })?
+                .collect::<Result<HashSet<String>, _>>()
+            })
+            .map_err(|e| DBAccessManager::map_err("while querying recent downloads", Some(e)))?;
+        let new_songs: HashSet<&String> = song_ids
+            .iter()
+            .copied()
+            .filter(|id| !recent_songs.contains(id.as_str()))
+            .collect();
+        if daily_limit > 0 && recent_songs.len() + new_songs.len() > daily_limit {
+            return Err(ZrcDBError::DownloadLimitReached(daily_limit));
+        }
+        for song_id in new_songs {
+            tx.execute(sql_stmt::INSERT_DOWNLOAD_RECORD, params![user_id, song_id, now])
+                .map_err(|e| DBAccessManager::map_err("while recording download", Some(e)))?;
+        }
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit download records", Some(e)))
+    }
+}
+
+// ----------------------------------------------------------------------------
+/// Song installation.
+impl DBAccessManager {
+    /// Add song and its charts to database, putting it into a pack or single
+    /// list. Existing song with the same id is replaced only if `replace` is
+    /// set. `write_files` is called before changes are committed, nothing is
+    /// added to database if it fails.
+    pub fn install_song<F>(&mut self, song: &SongEntry, replace: bool, write_files: F) -> ZrcDBResult<()>
+    where
+        F: FnOnce() -> Result<(), String>,
+    {
+        let remote_dl = if song.remote_dl { "t" } else { "" };
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for song installing", Some(e))
+        })?;
+        let exists = tx
+            .query_row(sql_stmt::CHECK_SONG_EXISTS, params![song.song_id], |row| {
+                row.get::<usize, bool>(0)
+            })
+            .map_err(|e| DBAccessManager::map_err("while checking song existence", Some(e)))?;
+        if exists && !replace {
+            return Err(ZrcDBError::SongExists(song.song_id.clone()));
+        }
+        match &song.shelf {
+            SongShelf::Pack(pack_name) => {
+                let pack_exists = tx
+                    .query_row(sql_stmt::CHECK_PACK_EXISTS, params![pack_name], |row| {
+                        row.get::<usize, bool>(0)
+                    })
+                    .map_err(|e| DBAccessManager::map_err("while checking pack existence", Some(e)))?;
+                if !pack_exists {
+                    return Err(ZrcDBError::ItemNotAvailable(format!("pack '{}'", pack_name)));
+                }
+            }
+            SongShelf::Single(price) => {
+                tx.execute(sql_stmt::INSERT_SINGLE, params![song.song_id])
+                    .and_then(|_| tx.execute(sql_stmt::INSERT_SINGLE_PRICE, params![song.song_id, price]))
+                    .map_err(|e| DBAccessManager::map_err("while adding song to single list", Some(e)))?;
+            }
+        }
+
+        tx.execute(
+            sql_stmt::INSERT_SONG,
+            params![
+                song.song_id,
+                song.title_en,
+                song.title_ja,
+                song.pack_name(),
+                song.checksum,
+                remote_dl
+            ],
+        )
+        .map_err(|e| {
+            DBAccessManager::map_err(&format!("while inserting song '{}'", song.song_id), Some(e))
+        })?;
+        tx.execute(sql_stmt::DELETE_SONG_CHARTS, params![song.song_id])
+            .map_err(|e| DBAccessManager::map_err("while removing old charts", Some(e)))?;
+        for chart in &song.charts {
+            tx.execute(
+                sql_stmt::INSERT_CHART,
+                params![song.song_id, chart.difficulty, chart.rating, chart.checksum, remote_dl],
+            )
+            .map_err(|e| {
+                DBAccessManager::map_err(
+                    &format!("while inserting chart {} of '{}'", chart.difficulty, song.song_id),
+                    Some(e),
+                )
+            })?;
+        }
+        write_files().map_err(ZrcDBError::Other)?;
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit song installing", Some(e)))
+    }
+}
+
+// ----------------------------------------------------------------------------
+/// Serial code redemption.
+impl DBAccessManager {
+    /// Create a redemption code granting given items. `max_use` of 0 means
+    /// the code can be used by any number of users, each of them once.
+    pub fn create_redeem_code(
+        &mut self,
+        code: &str,
+        max_use: usize,
+        expire_at: Option<i64>,
+        items: &[ItemGrant],
+    ) -> ZrcDBResult<()> {
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for creating code", Some(e))
+        })?;
+        tx.execute(sql_stmt::INSERT_REDEEM_CODE, params![code, max_use, expire_at])
+            .map_err(|e| {
+                DBAccessManager::map_err(&format!("while inserting code '{}'", code), Some(e))
+            })?;
+        for item in items {
+            tx.execute(
+                sql_stmt::INSERT_REDEEM_CODE_ITEM,
+                params![code, item.item_type.as_str(), item.id, item.amount],
+            )
+            .map_err(|e| {
+                DBAccessManager::map_err(&format!("while inserting items of code '{}'", code), Some(e))
+            })?;
+        }
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit redemption code", Some(e)))
+    }
+
+    /// Redeem a code for user, returning items granted.
+    pub fn redeem_code(&mut self, user_id: isize, code: &str) -> ZrcDBResult<Vec<ItemGrant>> {
+        use rusqlite::OptionalExtension;
+        use std::time::SystemTime;
+
+        let code = code.trim().to_uppercase();
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for redemption", Some(e))
+        })?;
+        let (max_use, used_count, expire_at, redeemed) = tx
+            .query_row(sql_stmt::QUERY_REDEEM_CODE, params![code, user_id], |row| {
+                Ok((
+                    row.get::<&str, usize>("max_use")?,
+                    row.get::<&str, usize>("used_count")?,
+                    row.get::<&str, i64>("expire_at")?,
+                    row.get::<&str, bool>("redeemed")?,
+                ))
+            })
+            .optional()
+            .map_err(|e| DBAccessManager::map_err("while querying redemption code", Some(e)))?
+            .ok_or(ZrcDBError::InvalidSerialNumber)?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        if expire_at != 0 && expire_at < now {
+            return Err(ZrcDBError::InvalidSerialNumber);
+        }
+        if redeemed || (max_use != 0 && used_count >= max_use) {
+            return Err(ZrcDBError::SerialNumberUsed);
+        }
+
+        let items = item::query_items(&tx, sql_stmt::QUERY_REDEEM_CODE_ITEM, &code)?;
+        for item in &items {
+            item.grant(&tx, user_id)?;
+        }
+
+        tx.execute(sql_stmt::USE_REDEEM_CODE, params![code])
+            .map_err(|e| DBAccessManager::map_err("while updating code usage", Some(e)))?;
+        tx.execute(sql_stmt::INSERT_REDEEM_RECORD, params![code, user_id, now])
+            .map_err(|e| DBAccessManager::map_err("while recording redemption", Some(e)))?;
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit redemption", Some(e)))?;
+        Ok(items)
+    }
+}
+
+// ----------------------------------------------------------------------------
+/// Presents.
+impl DBAccessManager {
+    pub fn get_presents(&self, user_id: isize) -> ZrcDBResult<Vec<Present>> {
+        Present::get_user_presents(&self.connection, user_id, chrono::Utc::now().timestamp(), None)
+    }
+
+    /// Grant all items in a present to user and mark it as claimed.
+    pub fn claim_present(&mut self, user_id: isize, present_id: &str) -> ZrcDBResult<Vec<ItemGrant>> {
+        let now = chrono::Utc::now().timestamp();
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for claiming present", Some(e))
+        })?;
+        let present = Present::get_user_presents(&tx, user_id, now, Some(present_id))?
+            .pop()
+            .ok_or_else(|| ZrcDBError::ItemNotAvailable(format!("present '{}'", present_id)))?;
+        for item in &present.items {
+            item.grant(&tx, user_id)?;
+        }
+        tx.execute(sql_stmt::INSERT_PRESENT_CLAIM, params![present_id, user_id, now])
+            .map_err(|e| DBAccessManager::map_err("while marking present as claimed", Some(e)))?;
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit present claiming", Some(e)))?;
+        Ok(present.items)
+    }
+
+    /// Send a present to users matching filter, return number of receivers,
+    /// or `None` if it's sent to everyone. `expire_ts` is in seconds, 0 for
+    /// never.
+    pub fn send_present(
+        &mut self,
+        present_id: &str,
+        description: &str,
+        expire_ts: i64,
+        items: &[ItemGrant],
+        filter: &PresentFilter,
+    ) -> ZrcDBResult<Option<usize>> {
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for sending present", Some(e))
+        })?;
+        tx.execute(
+            sql_stmt::INSERT_PRESENT,
+            params![present_id, description, expire_ts, chrono::Utc::now().timestamp()],
+        )
+        .map_err(|e| {
+            DBAccessManager::map_err(&format!("while inserting present '{}'", present_id), Some(e))
+        })?;
+        for item in items {
+            tx.execute(
+                sql_stmt::INSERT_PRESENT_ITEM,
+                params![present_id, item.item_type.as_str(), item.id, item.amount],
+            )
+            .map_err(|e| DBAccessManager::map_err("while inserting present items", Some(e)))?;
+        }
+
+        let receiver_count = if filter.is_empty() {
+            None
+        } else {
+            let user_ids = filter.query_user_ids(&tx)?;
+            if user_ids.is_empty() {
+                return Err(ZrcDBError::DataNotFound(
+                    "no user matches present filter".to_string(),
+                ));
+            }
+            for user_id in &user_ids {
+                tx.execute(sql_stmt::INSERT_PRESENT_TARGET, params![present_id, user_id])
+                    .map_err(|e| DBAccessManager::map_err("while inserting present target", Some(e)))?;
+            }
+            Some(user_ids.len())
+        };
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit present", Some(e)))?;
+        Ok(receiver_count)
+    }
 }
 
 // ----------------------------------------------------------------------------
 /// Character management.
 impl DBAccessManager {
-    fn add_all_character_for_user(
+    fn add_initial_character_for_user(
         tx: &rusqlite::Transaction,
         user_id: isize,
-    ) -> Result<(), rusqlite::Error> {
-        let mut stmt = tx.prepare(sql_stmt::ADD_CHAR_FOR_NEW_USER)?;
-        stmt.execute(params![user_id])?;
+    ) -> ZrcDBResult<()> {
+        for part_id in character::INITIAL_PARTNERS.iter() {
+            ItemGrant {
+                item_type: GrantType::Character,
+                id: part_id.to_string(),
+                amount: 1,
+            }
+            .grant(tx, user_id)?;
+        }
         Ok(())
     }
 
+    fn check_character_owned(&self, user_id: isize, char_id: isize) -> ZrcDBResult<()> {
+        let is_owned = self
+            .connection
+            .query_row(sql_stmt::CHECK_CHARACTER_OWNED, params![user_id, char_id], |row| {
+                row.get::<usize, bool>(0)
+            })
+            .map_err(|e| DBAccessManager::map_err("while checking partner possession", Some(e)))?;
+        if is_owned {
+            Ok(())
+        } else {
+            Err(ZrcDBError::CharacterNotOwned(char_id))
+        }
+    }
+
     pub fn change_character(
         &self,
         user_id: isize,
         char_id: isize,
         skill_sealed: bool,
     ) -> ZrcDBResult<usize> {
+        self.check_character_owned(user_id, char_id)?;
         let skill_sealed = if skill_sealed { "t" } else { "f" };
         let mut stmt = self
             .connection
@@ -538,6 +1181,30 @@ impl DBAccessManager {
         Ok(stats)
     }
 
+    /// Uncap a partner for user, return its new stats and user's remaining cores.
+    pub fn uncap_character(
+        &mut self,
+        user_id: isize,
+        part_id: isize,
+    ) -> ZrcDBResult<(CharacterStatses, Vec<CoreInfo>)> {
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for uncapping", Some(e))
+        })?;
+        character::uncap_character(&tx, user_id, part_id)?;
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commit uncapping", Some(e)))?;
+
+        let stats = self.get_char_statses(user_id, Some(part_id)).map_err(|e| {
+            DBAccessManager::map_err(
+                "while querying character statistics after uncapping",
+                Some(e),
+            )
+        })?;
+        let cores = CoreInfo::get_user_cores(self, user_id)
+            .map_err(|e| DBAccessManager::map_err("while querying cores after uncapping", Some(e)))?;
+        Ok((stats, cores))
+    }
+
     fn get_char_statses(
         &self,
         user_id: isize,
@@ -622,12 +1289,7 @@ impl DBAccessManager {
             .map_err(|e| DBAccessManager::map_err("while signing up", Some(e)))?;
         }
 
-        DBAccessManager::add_all_character_for_user(&tx, user_id).map_err(|e| {
-            DBAccessManager::map_err(
-                &format!("while adding character for user '{}'", user_id),
-                Some(e),
-            )
-        })?;
+        DBAccessManager::add_initial_character_for_user(&tx, user_id)?;
 
         tx.commit()
             .map_err(|e| DBAccessManager::map_err("while commit sing up data", Some(e)))?;
@@ -655,6 +1317,16 @@ impl DBAccessManager {
         Ok(info)
     }
 
+    pub fn get_user_id_by_code(&self, user_code: isize) -> ZrcDBResult<isize> {
+        use rusqlite::OptionalExtension;
+
+        self.connection
+            .query_row(sql_stmt::GET_FRIEND_ID, params![user_code], |row| row.get(0))
+            .optional()
+            .map_err(|e| DBAccessManager::map_err("while querying user id", Some(e)))?
+            .ok_or_else(|| ZrcDBError::DataNotFound(format!("user with code '{}'", user_code)))
+    }
+
     pub fn get_minimum_user_info(&self, user_id: isize) -> ZrcDBResult<UserInfoMinimum> {
         UserInfoMinimum::new(&self, user_id).map_err(|e| {
             DBAccessManager::map_err(
@@ -689,10 +1361,10 @@ impl DBAccessManager {
                         item_type: "single".to_string(),
                         is_available: true,
                     }],
-                    orig_price: 0,
-                    price: 0,
-                    discount_from: 1491868801000,
-                    discount_to: 1491868801000,
+                    orig_price: row.get("orig_price")?,
+                    price: row.get("price")?,
+                    discount_from: row.get("discount_from")?,
+                    discount_to: row.get("discount_to")?,
                 })
             })
             .map_err(|e| {
@@ -711,7 +1383,11 @@ impl DBAccessManager {
             .map_err(|e| DBAccessManager::map_err("while querying map info", Some(e)))
     }
 
+    /// Set user's favorite partner, `-1` clears it.
     pub fn set_favorite_character(&self, user_id: isize, char_id: isize) -> ZrcDBResult<usize> {
+        if char_id != -1 {
+            self.check_character_owned(user_id, char_id)?;
+        }
         let mut stmt = self
             .connection
             .prepare(sql_stmt::SET_FAVORITE_CHARACTER)
@@ -780,6 +1456,21 @@ impl DBAccessManager {
             .map_err(|e| DBAccessManager::map_err("while uploading score", Some(e)))
     }
 
+    /// Insert a score newly played by user, and give user's active partner
+    /// experience for it.
+    pub fn play_upload(
+        &mut self,
+        score: &ScoreRecord,
+        user_id: isize,
+    ) -> ZrcDBResult<HashMap<String, isize>> {
+        score::play_upload(self, score, user_id).map_err(|e| {
+            DBAccessManager::map_err(
+                &format!("while uploading play of user '{}'", user_id),
+                Some(e),
+            )
+        })
+    }
+
     pub fn get_best_scores_with_iden(
         &self,
         user_id: isize,
@@ -795,10 +1486,25 @@ impl DBAccessManager {
     }
 
     pub fn score_lookup(&self, user_id: isize) -> ZrcDBResult<Vec<LookupedScore>> {
-        score::score_lookup(self, user_id)
+        score::score_lookup(self, user_id, score::LOOKUP_LIMIT)
             .map_err(|e| DBAccessManager::map_err("while processing score lookup", Some(e)))
     }
 
+    /// Line up best scores of a user and one of their friends chart by chart.
+    pub fn score_compare(&self, user_id: isize, friend_id: isize) -> ZrcDBResult<Vec<ComparedScore>> {
+        let is_friend = self
+            .connection
+            .query_row(sql_stmt::CHECK_IF_FRIEND_EXISTS, params![user_id, friend_id], |row| {
+                row.get::<usize, bool>(0)
+            })
+            .map_err(|e| DBAccessManager::map_err("while checking friend relation", Some(e)))?;
+        if !is_friend {
+            return Err(ZrcDBError::NotFriend);
+        }
+        score::score_compare(self, user_id, friend_id)
+            .map_err(|e| DBAccessManager::map_err("while processing score comparison", Some(e)))
+    }
+
     pub fn get_r10_and_b30(&self, user_id: isize) -> ZrcDBResult<(f64, f64)> {
         self._get_r10_and_b30(user_id)
             .map_err(|e| DBAccessManager::map_err("while querying r10 and b30", Some(e)))
@@ -812,6 +1518,46 @@ impl DBAccessManager {
     }
 }
 
+// States of a row in friend list besides being friend.
+const FRIEND_PENDING: &str = "pending";
+const FRIEND_BLOCKED: &str = "blocked";
+
+// ----------------------------------------------------------------------------
+/// Moving users between server instances.
+impl DBAccessManager {
+    pub fn export_user(&self, user_id: isize) -> ZrcDBResult<UserArchive> {
+        archive::export_user(self, user_id)
+            .map_err(|e| DBAccessManager::map_err("while exporting user", Some(e)))
+    }
+
+    /// Create a new user from archive, with new user id, and new user code if
+    /// the original one is taken.
+    pub fn import_user(&mut self, archive: &UserArchive) -> ZrcDBResult<ImportReport> {
+        archive::import_user(self, archive)
+    }
+}
+
+// ----------------------------------------------------------------------------
+/// Game progress backup history.
+impl DBAccessManager {
+    pub fn get_backup_history(&self, user_id: isize) -> ZrcDBResult<Vec<save::BackupEntry>> {
+        save::get_backup_history(self, user_id)
+            .map_err(|e| DBAccessManager::map_err("while querying backup history", Some(e)))
+    }
+
+    /// Make a backup in history the one served on next download.
+    pub fn restore_backup(&self, user_id: isize, backup_id: isize) -> ZrcDBResult<()> {
+        let restored = self
+            .connection
+            .execute(sql_stmt::RESTORE_BACKUP, params![user_id, backup_id])
+            .map_err(|e| DBAccessManager::map_err("while restoring backup", Some(e)))?;
+        if restored == 0 {
+            return Err(ZrcDBError::NoSuchBackup(backup_id));
+        }
+        Ok(())
+    }
+}
+
 // ----------------------------------------------------------------------------
 /// Friend system
 impl DBAccessManager {
@@ -862,6 +1608,36 @@ impl DBAccessManager {
         Ok(())
     }
 
+    // State of row from `user_id` to `friend_id` in friend list, `None` if
+    // there is no such row.
+    fn get_friend_state(
+        tx: &rusqlite::Transaction,
+        user_id: isize,
+        friend_id: isize,
+    ) -> ZrcDBResult<Option<String>> {
+        use rusqlite::OptionalExtension;
+
+        tx.query_row(sql_stmt::GET_FRIEND_STATE, params![user_id, friend_id], |row| row.get(0))
+            .optional()
+            .map_err(|e| DBAccessManager::map_err("while querying friend state", Some(e)))
+    }
+
+    fn check_friend_list_full(tx: &rusqlite::Transaction, user_id: isize) -> ZrcDBResult<()> {
+        let is_full = tx
+            .query_row(sql_stmt::CHECK_FRIEND_LIST_FULL, params![user_id], |row| {
+                row.get::<usize, bool>(0)
+            })
+            .map_err(|e| DBAccessManager::map_err("while checking friend list capacity", Some(e)))?;
+        if is_full {
+            return Err(ZrcDBError::FriendListFull);
+        }
+        Ok(())
+    }
+
+    /// Add user with given friend code as friend. If that user requires
+    /// approval, a friend request is sent instead. Adding someone who has
+    /// already sent a request to user, or already has user as friend, makes
+    /// them mutual friends without approval.
     pub fn add_friend(&mut self, user_id: isize, friend_code: isize) -> ZrcDBResult<()> {
         log::debug!(
             "adding friend for user '{}' with friend code '{}'",
@@ -877,40 +1653,48 @@ impl DBAccessManager {
         if friend_id == user_id {
             return Err(ZrcDBError::SelfFriend);
         }
-        match DBAccessManager::check_if_friend_exists(&tx, user_id, friend_id) {
-            Ok(check) => {
-                if check {
-                    return Err(ZrcDBError::FriendExists);
-                }
+        let outgoing = DBAccessManager::get_friend_state(&tx, user_id, friend_id)?;
+        let incoming = DBAccessManager::get_friend_state(&tx, friend_id, user_id)?;
+        if outgoing.as_deref() == Some(FRIEND_BLOCKED) || incoming.as_deref() == Some(FRIEND_BLOCKED) {
+            return Err(ZrcDBError::FriendBlocked);
+        }
+        if outgoing.is_some() {
+            return Err(ZrcDBError::FriendExists);
+        }
+        DBAccessManager::check_friend_list_full(&tx, user_id)?;
+
+        let state = match incoming.as_deref() {
+            Some(FRIEND_PENDING) => {
+                tx.execute(sql_stmt::ACCEPT_FRIEND_REQUEST, params![friend_id, user_id])
+                    .map_err(|e| DBAccessManager::map_err("while accepting friend request", Some(e)))?;
+                ""
             }
-            Err(e) => {
-                return Err(DBAccessManager::map_err(
-                    "while check friend existance",
-                    Some(e),
-                ))
+            // the other user has already added this user as friend
+            Some(_) => "",
+            None => {
+                let approval_required = tx
+                    .query_row(sql_stmt::CHECK_FRIEND_APPROVAL_REQUIRED, params![friend_id], |row| {
+                        row.get::<usize, bool>(0)
+                    })
+                    .map_err(|e| DBAccessManager::map_err("while checking friend setting", Some(e)))?;
+                if approval_required {
+                    FRIEND_PENDING
+                } else {
+                    ""
+                }
             }
-        }
+        };
         {
             let mut stmt = tx.prepare(sql_stmt::ADD_FRIEND).map_err(|e| {
                 DBAccessManager::map_err("while preparing statement for adding friend", Some(e))
             })?;
-            stmt.execute(params![user_id, friend_id])
+            stmt.execute(params![user_id, friend_id, state])
                 .map_err(|e| DBAccessManager::map_err("while adding friend", Some(e)))?;
         }
-        match DBAccessManager::check_if_friend_exists(&tx, friend_id, user_id) {
-            Ok(check) => {
-                if check {
-                    DBAccessManager::set_mutual(&tx, user_id, friend_id).map_err(|e| {
-                        DBAccessManager::map_err("while trying to set mutual friend", Some(e))
-                    })?;
-                }
-            }
-            Err(e) => {
-                return Err(DBAccessManager::map_err(
-                    "while check friend existance",
-                    Some(e),
-                ))
-            }
+        if state.is_empty() && incoming.is_some() {
+            DBAccessManager::set_mutual(&tx, user_id, friend_id).map_err(|e| {
+                DBAccessManager::map_err("while trying to set mutual friend", Some(e))
+            })?;
         }
         tx.commit().map_err(|e| {
             DBAccessManager::map_err("while commiting change of adding friend", Some(e))
@@ -918,6 +1702,108 @@ impl DBAccessManager {
         Ok(())
     }
 
+    /// Accept friend request sent by `requester_id`, both users become mutual
+    /// friends.
+    pub fn accept_friend_request(&mut self, user_id: isize, requester_id: isize) -> ZrcDBResult<()> {
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for accepting friend", Some(e))
+        })?;
+        let updated = tx
+            .execute(sql_stmt::ACCEPT_FRIEND_REQUEST, params![requester_id, user_id])
+            .map_err(|e| DBAccessManager::map_err("while accepting friend request", Some(e)))?;
+        if updated == 0 {
+            return Err(ZrcDBError::NoFriendRequest);
+        }
+        if DBAccessManager::get_friend_state(&tx, user_id, requester_id)?.is_none() {
+            DBAccessManager::check_friend_list_full(&tx, user_id)?;
+            tx.execute(sql_stmt::ADD_FRIEND, params![user_id, requester_id, ""])
+                .map_err(|e| DBAccessManager::map_err("while adding friend", Some(e)))?;
+        }
+        DBAccessManager::set_mutual(&tx, user_id, requester_id).map_err(|e| {
+            DBAccessManager::map_err("while trying to set mutual friend", Some(e))
+        })?;
+        tx.commit().map_err(|e| {
+            DBAccessManager::map_err("while commiting change of accepting friend", Some(e))
+        })
+    }
+
+    /// Reject friend request sent by `requester_id`.
+    pub fn reject_friend_request(&self, user_id: isize, requester_id: isize) -> ZrcDBResult<()> {
+        let deleted = self
+            .connection
+            .execute(sql_stmt::DELETE_FRIEND_REQUEST, params![requester_id, user_id])
+            .map_err(|e| DBAccessManager::map_err("while rejecting friend request", Some(e)))?;
+        if deleted == 0 {
+            return Err(ZrcDBError::NoFriendRequest);
+        }
+        Ok(())
+    }
+
+    /// Block a user, removing friendship and requests between the two. Blocked
+    /// user can't add user as friend, nor see user in their friend list.
+    pub fn block_user(&mut self, user_id: isize, target_id: isize) -> ZrcDBResult<()> {
+        if user_id == target_id {
+            return Err(ZrcDBError::SelfFriend);
+        }
+        self.get_minimum_user_info(target_id)?;
+        let tx = self.connection.transaction().map_err(|e| {
+            DBAccessManager::map_err("while opening transacation for blocking user", Some(e))
+        })?;
+        tx.execute(sql_stmt::DELETE_FRIEND, params![target_id, user_id])
+            .and_then(|_| tx.execute(sql_stmt::BLOCK_USER, params![user_id, target_id]))
+            .map_err(|e| DBAccessManager::map_err("while blocking user", Some(e)))?;
+        tx.commit()
+            .map_err(|e| DBAccessManager::map_err("while commiting change of blocking user", Some(e)))
+    }
+
+    pub fn unblock_user(&self, user_id: isize, target_id: isize) -> ZrcDBResult<()> {
+        self.connection
+            .execute(sql_stmt::UNBLOCK_USER, params![user_id, target_id])
+            .map_err(|e| DBAccessManager::map_err("while unblocking user", Some(e)))?;
+        Ok(())
+    }
+
+    // Minimum info of users whose id are returned by given statement, which
+    // takes user id as its only parameter.
+    fn get_user_infos_by(&self, stmt: &str, user_id: isize) -> ZrcDBResult<Vec<UserInfoMinimum>> {
+        let ids = {
+            let mut stmt = self.connection.prepare(stmt).map_err(|e| {
+                DBAccessManager::map_err("while preparing statement for user list", Some(e))
+            })?;
+            stmt.query_map(params![user_id], |row| row.get::<usize, isize>(0))
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| DBAccessManager::map_err("while querying user list", Some(e)))?
+        };
+        ids.into_iter().map(|id| self.get_minimum_user_info(id)).collect()
+    }
+
+    /// Users who sent friend requests to user, and users user sent requests to.
+    pub fn get_friend_requests(
+        &self,
+        user_id: isize,
+    ) -> ZrcDBResult<(Vec<UserInfoMinimum>, Vec<UserInfoMinimum>)> {
+        Ok((
+            self.get_user_infos_by(sql_stmt::GET_INCOMING_REQUEST_ID, user_id)?,
+            self.get_user_infos_by(sql_stmt::GET_OUTGOING_REQUEST_ID, user_id)?,
+        ))
+    }
+
+    pub fn get_blocked_users(&self, user_id: isize) -> ZrcDBResult<Vec<UserInfoMinimum>> {
+        self.get_user_infos_by(sql_stmt::GET_BLOCKED_ID, user_id)
+    }
+
+    /// Set friend list capacity of user with given user code.
+    pub fn set_max_friend(&self, user_code: isize, max_friend: usize) -> ZrcDBResult<()> {
+        let updated = self
+            .connection
+            .execute(sql_stmt::SET_MAX_FRIEND, params![max_friend, user_code])
+            .map_err(|e| DBAccessManager::map_err("while setting friend list capacity", Some(e)))?;
+        if updated == 0 {
+            return Err(ZrcDBError::DataNotFound(format!("user with code '{}'", user_code)));
+        }
+        Ok(())
+    }
+
     pub fn delete_friend(&mut self, user_id: isize, friend_id: isize) -> ZrcDBResult<()> {
         let tx = self.connection.transaction().map_err(|e| {
             DBAccessManager::map_err("while opening transacation for deleting friend", Some(e))
@@ -951,46 +1837,8 @@ impl DBAccessManager {
         Ok(())
     }
 
-    fn get_is_mutual(&self, user_id: isize, friend_id: isize) -> ZrcDBResult<bool> {
-        let mut stmt = self
-            .connection
-            .prepare(sql_stmt::GET_IS_MUTUAL)
-            .map_err(|e| {
-                DBAccessManager::map_err(
-                    "while preparing statement for getting is mutual info",
-                    Some(e),
-                )
-            })?;
-        stmt.query_row(params![user_id, friend_id], |row| {
-            Ok(row.get::<usize, String>(0)? == "t")
-        })
-        .map_err(|e| DBAccessManager::map_err("while getting is mutual", Some(e)))
-    }
-
     pub fn get_friend_list(&self, user_id: isize) -> ZrcDBResult<Vec<UserInfoMinimum>> {
-        let mut stmt = self
-            .connection
-            .prepare(sql_stmt::GET_ALL_FRIEND_ID)
-            .map_err(|e| {
-                DBAccessManager::map_err(
-                    "while preparing statement for querying all friend id",
-                    Some(e),
-                )
-            })?;
-        let mut rows = stmt
-            .query(params![user_id])
-            .map_err(|e| DBAccessManager::map_err("while querying all friend id", Some(e)))?;
-        let mut infoes = Vec::new();
-        while let Some(row) = rows.next().map_err(|e| {
-            DBAccessManager::map_err("while reading results of all friend id", Some(e))
-        })? {
-            let friend_id = row.get(0).map_err(|e| {
-                DBAccessManager::map_err("while reading results of all friend id", Some(e))
-            })?;
-            let mut info = self.get_minimum_user_info(friend_id)?;
-            info.is_mutual = self.get_is_mutual(user_id, friend_id)?;
-            infoes.push(info)
-        }
-        Ok(infoes)
+        UserInfoMinimum::get_friends(self, user_id)
+            .map_err(|e| DBAccessManager::map_err("while querying friend list", Some(e)))
     }
 }
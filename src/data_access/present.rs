@@ -0,0 +1,96 @@
+use super::*;
+
+/// A gift waiting in user's inbox, claimed as a whole.
+#[derive(Serialize)]
+pub struct Present {
+    pub present_id: String,
+    pub description: String,
+    /// Expire time in milliseconds as client expects, 0 for never.
+    pub expire_ts: i64,
+    pub items: Vec<ItemGrant>,
+}
+
+impl Present {
+    /// Unclaimed and unexpired presents sent to user, `now` is timestamp in
+    /// seconds. If `present_id` is given, only that present is looked up.
+    pub fn get_user_presents(
+        conn: &rusqlite::Connection,
+        user_id: isize,
+        now: i64,
+        present_id: Option<&str>,
+    ) -> ZrcDBResult<Vec<Self>> {
+        let mut stmt = conn.prepare(sql_stmt::QUERY_USER_PRESENT).map_err(|e| {
+            DBAccessManager::map_err("while preparing statement for presents", Some(e))
+        })?;
+        let rows = stmt
+            .query_map(params![user_id, now, present_id], |row| {
+                Ok((
+                    row.get::<&str, String>("present_id")?,
+                    row.get::<&str, String>("description")?,
+                    row.get::<&str, i64>("expire_ts")? * 1000,
+                ))
+            })
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| {
+                DBAccessManager::map_err(
+                    &format!("while querying presents of user '{}'", user_id),
+                    Some(e),
+                )
+            })?;
+
+        let mut presents = Vec::new();
+        for (present_id, description, expire_ts) in rows {
+            let items = item::query_items(conn, sql_stmt::QUERY_PRESENT_ITEM, &present_id)?;
+            presents.push(Present {
+                present_id,
+                description,
+                expire_ts,
+                items,
+            });
+        }
+        Ok(presents)
+    }
+}
+
+/// Condition for choosing receivers of a present, an empty filter sends it to
+/// everyone, including users who sign up later.
+#[derive(Default)]
+pub struct PresentFilter {
+    pub user_codes: Vec<isize>,
+    pub min_rating: Option<isize>,
+}
+
+impl PresentFilter {
+    pub fn is_empty(&self) -> bool {
+        self.user_codes.is_empty() && self.min_rating.is_none()
+    }
+
+    /// Id of all users matching this filter.
+    pub fn query_user_ids(&self, tx: &rusqlite::Transaction) -> ZrcDBResult<Vec<isize>> {
+        let code_list = self
+            .user_codes
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut conditions = Vec::new();
+        if !self.user_codes.is_empty() {
+            conditions.push(format!("user_code in ({})", code_list));
+        }
+        if let Some(rating) = self.min_rating {
+            conditions.push(format!("rating >= {}", rating));
+        }
+        let stmt = format!(
+            "{} where {}",
+            sql_stmt::QUERY_ALL_USER_ID,
+            conditions.join(" and ")
+        );
+
+        let mut stmt = tx.prepare(&stmt).map_err(|e| {
+            DBAccessManager::map_err("while preparing statement for present receivers", Some(e))
+        })?;
+        stmt.query_map([], |row| row.get(0))
+            .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| DBAccessManager::map_err("while querying present receivers", Some(e)))
+    }
+}
@@ -1,7 +1,6 @@
 use super::score::ScoreRecord;
 use super::*;
 use rusqlite::OptionalExtension;
-use std::fmt;
 use std::time::SystemTime;
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -134,8 +133,8 @@ impl BackupData {
             story: HashMap::new(),
             created_at: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis(),
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
             checksums: HashMap::new(),
         };
         data.scores.insert("".to_string(), Vec::new());
@@ -152,21 +151,86 @@ impl BackupData {
         data
     }
 
-    pub fn insert_game_progress(&self, conn: &DBAccessManager, user_id: isize) -> ZrcDBResult<()> {
-        let mut stmt = conn
-            .connection
-            .prepare(sql_stmt::INSERT_OTHER_BACKUP)
-            .unwrap();
-        stmt.execute(params![
+    /// Parse backup uploaded by client, where each section comes as JSON string
+    /// in `<section>_data`, along with MD5 of that string in
+    /// `<section>_checksum`. Sections with checksum given are rejected if it
+    /// doesn't match.
+    pub fn from_form(form: &HashMap<String, String>) -> ZrcDBResult<Self> {
+        let mut data = BackupData::new();
+        for (key, value) in form {
+            if let Some(section) = key.strip_suffix("_checksum") {
+                let content = form.get(&format!("{}_data", section)).ok_or_else(|| {
+                    ZrcDBError::CorruptedBackup(format!("{} data is missing", section))
+                })?;
+                if !value.eq_ignore_ascii_case(&checksum(content)) {
+                    return Err(ZrcDBError::CorruptedBackup(format!(
+                        "checksum mismatch for {} data",
+                        section
+                    )));
+                }
+            } else if let Some(section) = key.strip_suffix("_data") {
+                data.parse_section(section, value).map_err(|e| {
+                    ZrcDBError::CorruptedBackup(format!("invalid {} data, {}", section, e))
+                })?;
+            }
+        }
+        Ok(data)
+    }
+
+    fn parse_section(&mut self, section: &str, content: &str) -> serde_json::Result<()> {
+        match section {
+            "version" => self.version = serde_json::from_str(content)?,
+            "scores" => self.scores = serde_json::from_str(content)?,
+            "clearlamps" => self.clearlamps = serde_json::from_str(content)?,
+            "clearedsongs" => self.clearedsongs = serde_json::from_str(content)?,
+            "unlocklist" => self.unlocklist = serde_json::from_str(content)?,
+            "installid" => self.installid = serde_json::from_str(content)?,
+            "devicemodelname" => self.devicemodelname = serde_json::from_str(content)?,
+            "story" => self.story = serde_json::from_str(content)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Fill in checksum of each section for download, computed the same way as
+    /// client does on upload.
+    pub fn compute_checksums(&mut self) -> ZrcDBResult<()> {
+        let sections = [
+            ("version", section_to_json("version", &self.version)?),
+            ("scores", section_to_json("scores", &self.scores)?),
+            ("clearlamps", section_to_json("clearlamps", &self.clearlamps)?),
+            ("clearedsongs", section_to_json("clearedsongs", &self.clearedsongs)?),
+            ("unlocklist", section_to_json("unlocklist", &self.unlocklist)?),
+            ("installid", section_to_json("installid", &self.installid)?),
+            ("devicemodelname", section_to_json("devicemodelname", &self.devicemodelname)?),
+            ("story", section_to_json("story", &self.story)?),
+        ];
+        for (section, content) in sections.iter() {
+            self.checksums.insert(section.to_string(), checksum(content));
+        }
+        Ok(())
+    }
+
+    /// Save game progress as the one served to client, and add it to backup
+    /// history which keeps at most `history_size` entries per user.
+    pub fn insert_game_progress(
+        &self,
+        conn: &mut DBAccessManager,
+        user_id: isize,
+        history_size: usize,
+    ) -> ZrcDBResult<()> {
+        let unlocklist = self.unlocklist.get("").map(Vec::as_slice).unwrap_or_default();
+        let story = self.story.get("").map(Vec::as_slice).unwrap_or_default();
+        let values = params![
             user_id,
             self.version.val,
-            serde_json::to_string(&self.unlocklist[""]).unwrap(),
+            section_to_json("unlocklist", unlocklist)?,
             self.installid.val,
             self.devicemodelname.val,
-            serde_json::to_string(&self.story[""]).unwrap(),
+            section_to_json("story", story)?,
             (self.created_at / 1000) as i64,
-        ])
-        .map_err(|e| {
+        ];
+        let map_err = |e| {
             ZrcDBError::Internal(
                 format!(
                     "while inserting game pross backup data for user: {}",
@@ -174,7 +238,15 @@ impl BackupData {
                 ),
                 e,
             )
-        })?;
+        };
+        let tx = conn.connection.transaction().map_err(map_err)?;
+        tx.execute(sql_stmt::INSERT_OTHER_BACKUP, values)
+            .map_err(map_err)?;
+        tx.execute(sql_stmt::INSERT_BACKUP_HISTORY, values)
+            .map_err(map_err)?;
+        tx.execute(sql_stmt::TRIM_BACKUP_HISTORY, params![user_id, history_size])
+            .map_err(map_err)?;
+        tx.commit().map_err(map_err)?;
 
         Ok(())
     }
@@ -251,13 +323,12 @@ impl BackupData {
 }
 
 impl BackupData {
-    pub fn get_game_progress(&mut self, conn: &DBAccessManager, user_id: isize) -> bool {
-        let mut stmt = conn
+    /// Load game progress uploaded last time, `NoCloudData` if user has never
+    /// uploaded one.
+    pub fn get_game_progress(&mut self, conn: &DBAccessManager, user_id: isize) -> ZrcDBResult<()> {
+        let result = conn
             .connection
-            .prepare(sql_stmt::QUERY_BACKUP_DATA)
-            .unwrap();
-        let result = stmt
-            .query_row(params![user_id], |row| {
+            .query_row(sql_stmt::QUERY_BACKUP_DATA, params![user_id], |row| {
                 Ok((
                     row.get::<&str, isize>("version")?,
                     row.get::<&str, String>("unlocklist")?,
@@ -268,101 +339,82 @@ impl BackupData {
                 ))
             })
             .optional()
-            .unwrap();
-        match result {
-            None => false,
-            Some((version, unlocklist, installid, devicemodel, story, create_at)) => {
-                self.version.val = version;
-                self.unlocklist
-                    .insert("".to_string(), serde_json::from_str(&unlocklist).unwrap());
-                self.installid.val = installid;
-                self.devicemodelname.val = devicemodel;
-                self.story
-                    .insert("".to_string(), serde_json::from_str(&story).unwrap());
-                self.created_at = create_at as u128 * 1000;
-                true
-            }
-        }
+            .map_err(|e| DBAccessManager::map_err("while querying backup data", Some(e)))?;
+        let (version, unlocklist, installid, devicemodel, story, create_at) =
+            result.ok_or(ZrcDBError::NoCloudData)?;
+        self.version.val = version;
+        self.unlocklist.insert(
+            "".to_string(),
+            serde_json::from_str(&unlocklist).map_err(|e| {
+                ZrcDBError::CorruptedCloudData(format!("invalid unlocklist data, {}", e))
+            })?,
+        );
+        self.installid.val = installid;
+        self.devicemodelname.val = devicemodel;
+        self.story.insert(
+            "".to_string(),
+            serde_json::from_str(&story).map_err(|e| {
+                ZrcDBError::CorruptedCloudData(format!("invalid story data, {}", e))
+            })?,
+        );
+        self.created_at = create_at as u128 * 1000;
+        Ok(())
     }
 
-    pub fn get_score_data(&mut self, conn: &DBAccessManager, user_id: isize) {
-        let score_data = self.scores.get_mut("").unwrap();
-        let lamp_data = self.clearlamps.get_mut("").unwrap();
-        let cleared_song_data = self.clearedsongs.get_mut("").unwrap();
-        let records_and_time = conn.get_all_best_scores(user_id).unwrap();
+    pub fn get_score_data(&mut self, conn: &DBAccessManager, user_id: isize) -> ZrcDBResult<()> {
+        let records_and_time = conn
+            .get_all_best_scores(user_id)
+            .map_err(|e| DBAccessManager::map_err("while querying best scores for backup", Some(e)))?;
+        let score_data = self.scores.entry("".to_string()).or_default();
+        let lamp_data = self.clearlamps.entry("".to_string()).or_default();
+        let cleared_song_data = self.clearedsongs.entry("".to_string()).or_default();
         for (record, time) in records_and_time {
             score_data.push(ScoreData::from_record_time(&record, time));
             lamp_data.push(ClearLampData::from_record(&record));
             cleared_song_data.push(ClearedSongData::from_record(&record));
         }
+        Ok(())
     }
 }
 
-impl<'de> Deserialize<'de> for BackupData {
-    fn deserialize<D>(deserializer: D) -> Result<BackupData, D::Error>
-    where
-        D: serde::de::Deserializer<'de>,
-    {
-        struct FieldVisitor;
+fn section_to_json<T: Serialize + ?Sized>(section: &str, value: &T) -> ZrcDBResult<String> {
+    serde_json::to_string(value).map_err(|e| {
+        ZrcDBError::CorruptedCloudData(format!("failed to serialize {} data, {}", section, e))
+    })
+}
 
-        impl<'de> serde::de::Visitor<'de> for FieldVisitor {
-            type Value = BackupData;
+fn checksum(content: &str) -> String {
+    format!("{:x}", md5::compute(content))
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("a query string specifying song ids and whether url is needed.")
-            }
+/// An entry in backup history of a user.
+#[derive(Serialize, Debug)]
+pub struct BackupEntry {
+    pub backup_id: isize,
+    pub version: isize,
+    #[serde(rename = "devicemodelname")]
+    pub device_model_name: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: i64,
+    /// Whether this is the backup served on next download.
+    pub is_current: bool,
+}
 
-            fn visit_map<V>(self, mut map: V) -> Result<BackupData, V::Error>
-            where
-                V: serde::de::MapAccess<'de>,
-            {
-                let mut data = BackupData::new();
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        "version_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.version = serde_json::from_str(&value).unwrap();
-                        }
-                        "scores_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.scores = serde_json::from_str(&value).unwrap();
-                        }
-                        "clearlamps_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.clearlamps = serde_json::from_str(&value).unwrap();
-                        }
-                        "clearedsongs_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.clearedsongs = serde_json::from_str(&value).unwrap();
-                        }
-                        "unlocklist_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.unlocklist = serde_json::from_str(&value).unwrap();
-                        }
-                        "installid_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.installid = serde_json::from_str(&value).unwrap();
-                        }
-                        "devicemodelname_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.devicemodelname = serde_json::from_str(&value).unwrap();
-                        }
-                        "story_data" => {
-                            let value = map.next_value::<String>()?;
-                            data.story = serde_json::from_str(&value).unwrap();
-                        }
-                        _ => {
-                            if key.ends_with("_checksum") {
-                                let key = key.trim_end_matches("_checksum");
-                                let value = map.next_value::<String>()?;
-                                data.checksums.insert(key.to_string(), value);
-                            }
-                        }
-                    }
-                }
-                Ok(data)
-            }
-        }
-        deserializer.deserialize_identifier(FieldVisitor)
-    }
+pub fn get_backup_history(
+    conn: &DBAccessManager,
+    user_id: isize,
+) -> Result<Vec<BackupEntry>, rusqlite::Error> {
+    let mut stmt = conn
+        .connection
+        .prepare(sql_stmt::QUERY_BACKUP_HISTORY)?;
+    stmt.query_map(params![user_id], |row| {
+        Ok(BackupEntry {
+            backup_id: row.get("backup_id")?,
+            version: row.get("version")?,
+            device_model_name: row.get("devicemodel_name")?,
+            created_at: row.get::<&str, i64>("create_at")? * 1000,
+            is_current: row.get("is_current")?,
+        })
+    })
+    .and_then(|rows| rows.collect())
 }
@@ -5,9 +5,13 @@ const GRADE_STEPS: [isize; 7] = [
     0, 8_600_000, 8_900_000, 9_200_000, 9_500_000, 9_800_000, 9_900_000,
 ];
 
+/// Number of records shown on score lookup page.
+pub const LOOKUP_LIMIT: isize = 60;
+
 #[derive(Debug)]
 pub struct LookupedScore {
     pub title: String,
+    pub song_id: String,
     pub difficulty: &'static str,
     pub score: isize,
     pub shiny: isize,
@@ -41,6 +45,59 @@ impl LookupedScore {
         }
         DIFFS[difficulty as usize]
     }
+
+    /// Position of clear type from worst to best.
+    fn clear_rank(&self) -> usize {
+        lazy_static! {
+            static ref CLEAR_RANK: [&'static str; 6] = [
+                "track-lost",
+                "easy-clear",
+                "normal-clear",
+                "hard-clear",
+                "full-recall",
+                "pure-memory",
+            ];
+        };
+        CLEAR_RANK
+            .iter()
+            .position(|clear_type| *clear_type == self.clear_type)
+            .unwrap_or(0)
+    }
+}
+
+/// Best scores of two users on the same chart, either of them may have not
+/// played it yet.
+#[derive(Debug)]
+pub struct ComparedScore {
+    pub title: String,
+    pub difficulty: &'static str,
+    pub base_rating: f64,
+    pub mine: Option<LookupedScore>,
+    pub theirs: Option<LookupedScore>,
+}
+
+impl ComparedScore {
+    /// Own score minus friend's score, `None` if chart is played by only one
+    /// of them.
+    pub fn score_delta(&self) -> Option<isize> {
+        match (&self.mine, &self.theirs) {
+            (Some(mine), Some(theirs)) => Some(mine.score - theirs.score),
+            _ => None,
+        }
+    }
+
+    /// 1 if own clear type is better than friend's, -1 if worse, 0 if the same
+    /// or chart is played by only one of them.
+    pub fn clear_cmp(&self) -> i8 {
+        match (&self.mine, &self.theirs) {
+            (Some(mine), Some(theirs)) => match mine.clear_rank().cmp(&theirs.clear_rank()) {
+                std::cmp::Ordering::Greater => 1,
+                std::cmp::Ordering::Less => -1,
+                std::cmp::Ordering::Equal => 0,
+            },
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -96,6 +153,8 @@ impl ScoreRecord {
         grade as u8
     }
 
+    /// Potential of this score on the chart, `QueryReturnedNoRows` if chart
+    /// has no base rating.
     fn score2rating(&self, tx: &PooledSqlite) -> Result<f64, rusqlite::Error> {
         let base_rating: f64 = tx.query_row(
             sql_stmt::BASE_RATING,
@@ -423,9 +482,36 @@ pub fn score_upload(
     user_id: isize,
     time: Option<&i64>,
 ) -> Result<HashMap<String, isize>, rusqlite::Error> {
-    let mut result = HashMap::new();
     let rating = score_record.score2rating(&conn.connection)?;
     let tx = conn.connection.transaction()?;
+    let result = insert_score(&tx, score_record, user_id, time, rating)?;
+    tx.commit()?;
+    Ok(result)
+}
+
+/// Insert a newly played score and give user's active partner experience for
+/// it in the same transaction.
+pub fn play_upload(
+    conn: &mut DBAccessManager,
+    score_record: &ScoreRecord,
+    user_id: isize,
+) -> Result<HashMap<String, isize>, rusqlite::Error> {
+    let rating = score_record.score2rating(&conn.connection)?;
+    let tx = conn.connection.transaction()?;
+    let result = insert_score(&tx, score_record, user_id, None, rating)?;
+    character::gain_partner_exp(&tx, user_id, character::play_exp(rating))?;
+    tx.commit()?;
+    Ok(result)
+}
+
+fn insert_score(
+    tx: &rusqlite::Transaction,
+    score_record: &ScoreRecord,
+    user_id: isize,
+    time: Option<&i64>,
+    rating: f64,
+) -> Result<HashMap<String, isize>, rusqlite::Error> {
+    let mut result = HashMap::new();
     let time_played: i64;
     match time {
         Some(t) => time_played = *t,
@@ -436,11 +522,10 @@ pub fn score_upload(
                 .as_secs() as i64;
         }
     }
-    score_record.insert_score_record(&tx, user_id, time_played, rating)?;
-    score_record.update_best_score(&tx, user_id, time_played)?;
-    score_record.update_recent_score(&tx, user_id, time_played, rating)?;
-    let rating = update_player_rating(&tx, user_id)?;
-    tx.commit()?;
+    score_record.insert_score_record(tx, user_id, time_played, rating)?;
+    score_record.update_best_score(tx, user_id, time_played)?;
+    score_record.update_recent_score(tx, user_id, time_played, rating)?;
+    let rating = update_player_rating(tx, user_id)?;
     result.insert("user_rating".to_string(), rating);
     Ok(result)
 }
@@ -461,7 +546,6 @@ pub fn get_best_scores_with_iden(
     conn: &DBAccessManager,
     user_id: isize,
 ) -> Result<HashMap<String, isize>, rusqlite::Error> {
-    let mut scores = HashMap::new();
     let mut stmt = conn
         .connection
         .prepare(sql_stmt::QUERY_BEST_SCORE_WITH_IDEN)?;
@@ -469,12 +553,7 @@ pub fn get_best_scores_with_iden(
         .query_map(params![user_id], |row| {
             Ok((row.get("iden")?, row.get("score")?))
         })?;
-
-    for score in results {
-        let score = score.unwrap();
-        scores.insert(score.0, score.1);
-    }
-    Ok(scores)
+    results.collect()
 }
 
 pub fn get_all_best_scores(
@@ -499,20 +578,22 @@ pub fn get_all_best_scores(
             record.clear_type = row.get("clear_type")?;
             Ok((record, row.get("played_date")?))
         })?;
-    Ok(results.into_iter().map(|x| x.unwrap()).collect())
+    results.collect()
 }
 
 pub fn score_lookup(
     conn: &DBAccessManager,
     user_id: isize,
+    limit: isize,
 ) -> Result<Vec<LookupedScore>, rusqlite::Error> {
     let mut stmt = conn
         .connection
         .prepare(sql_stmt::QUERY_BEST_SCORE_FOR_LOOKUP)?;
     let results = stmt
-        .query_map(params![user_id], |row| {
+        .query_map(params![user_id, limit], |row| {
             let record = LookupedScore {
                 title: row.get("title")?,
+                song_id: row.get("song_id")?,
                 difficulty: LookupedScore::get_diff_str(row.get("difficulty")?),
                 score: row.get("score")?,
                 shiny: row.get("shiny_pure")?,
@@ -529,3 +610,40 @@ pub fn score_lookup(
         })?;
     Ok(results.into_iter().map(|x| x.unwrap()).collect())
 }
+
+pub fn score_compare(
+    conn: &DBAccessManager,
+    user_id: isize,
+    friend_id: isize,
+) -> Result<Vec<ComparedScore>, rusqlite::Error> {
+    let mut theirs: HashMap<(String, &'static str), LookupedScore> = score_lookup(conn, friend_id, -1)?
+        .into_iter()
+        .map(|record| ((record.song_id.clone(), record.difficulty), record))
+        .collect();
+    let mut compared: Vec<ComparedScore> = score_lookup(conn, user_id, -1)?
+        .into_iter()
+        .map(|mine| ComparedScore {
+            title: mine.title.clone(),
+            difficulty: mine.difficulty,
+            base_rating: mine.base_rating,
+            theirs: theirs.remove(&(mine.song_id.clone(), mine.difficulty)),
+            mine: Some(mine),
+        })
+        .collect();
+    compared.extend(theirs.into_values().map(|record| ComparedScore {
+        title: record.title.clone(),
+        difficulty: record.difficulty,
+        base_rating: record.base_rating,
+        mine: None,
+        theirs: Some(record),
+    }));
+    // charts played by both come first, harder charts first within each group
+    compared.sort_by(|a, b| {
+        b.score_delta()
+            .is_some()
+            .cmp(&a.score_delta().is_some())
+            .then(b.base_rating.partial_cmp(&a.base_rating).unwrap_or(std::cmp::Ordering::Equal))
+            .then_with(|| a.title.cmp(&b.title))
+    });
+    Ok(compared)
+}
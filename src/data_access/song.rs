@@ -0,0 +1,35 @@
+/// Where an installed song is sold.
+pub enum SongShelf {
+    /// Song is part of an existing pack.
+    Pack(String),
+    /// Song is sold alone at given price.
+    Single(isize),
+}
+
+/// A chart of song to be installed.
+pub struct ChartEntry {
+    pub difficulty: i8,
+    pub rating: f64,
+    pub checksum: String,
+}
+
+/// Database records of a song to be installed.
+pub struct SongEntry {
+    pub song_id: String,
+    pub title_en: String,
+    pub title_ja: String,
+    pub shelf: SongShelf,
+    pub checksum: String,
+    pub remote_dl: bool,
+    pub charts: Vec<ChartEntry>,
+}
+
+impl SongEntry {
+    /// Value of `pack_name` column in `song` table, singles use `single`.
+    pub fn pack_name(&self) -> &str {
+        match &self.shelf {
+            SongShelf::Pack(name) => name,
+            SongShelf::Single(_) => "single",
+        }
+    }
+}
@@ -1,3 +1,220 @@
+// schema
+// ============================================================================
+// Tables added on top of the original database layout, created at startup if
+// they are missing.
+pub const CREATE_CORE_ITEM: &str = r#"
+    create table if not exists core_item (
+        user_id integer not null,
+        core_type text not null,
+        amount integer not null default 0,
+        primary key (user_id, core_type)
+    );
+"#;
+
+pub const CREATE_PART_UNCAP_CORE: &str = r#"
+    create table if not exists part_uncap_core (
+        part_id integer not null,
+        core_type text not null,
+        amount integer not null,
+        primary key (part_id, core_type)
+    );
+"#;
+
+pub const CREATE_PLAYER_FRAGMENT: &str = r#"
+    create table if not exists player_fragment (
+        user_id integer primary key,
+        amount integer not null default 0
+    );
+"#;
+
+pub const CREATE_REDEEM_CODE: &str = r#"
+    create table if not exists redeem_code (
+        code text primary key,
+        max_use integer not null default 1,
+        used_count integer not null default 0,
+        expire_at integer
+    );
+    create table if not exists redeem_code_item (
+        code text not null,
+        item_type text not null,
+        item_id text not null default '',
+        amount integer not null default 1
+    );
+    create table if not exists redeem_record (
+        code text not null,
+        user_id integer not null,
+        redeem_at integer not null,
+        primary key (code, user_id)
+    );
+"#;
+
+// Present without any row in `present_target` is sent to everyone. Times are
+// in seconds, `expire_ts` is 0 for present never expiring.
+pub const CREATE_PRESENT: &str = r#"
+    create table if not exists present (
+        present_id text primary key,
+        description text not null default '',
+        expire_ts integer not null default 0,
+        create_at integer not null
+    );
+    create table if not exists present_item (
+        present_id text not null,
+        item_type text not null,
+        item_id text not null default '',
+        amount integer not null default 1
+    );
+    create table if not exists present_target (
+        present_id text not null,
+        user_id integer not null,
+        primary key (present_id, user_id)
+    );
+    create table if not exists present_claim (
+        present_id text not null,
+        user_id integer not null,
+        claim_at integer not null,
+        primary key (present_id, user_id)
+    );
+"#;
+
+// Voice list of each partner. When empty, it's filled with the voice set
+// every partner listed in `part_voice` used to share.
+pub const CREATE_PARTNER_VOICE: &str = r#"
+    create table if not exists partner_voice (
+        part_id integer not null,
+        voice_id integer not null,
+        primary key (part_id, voice_id)
+    );
+    with voice(voice_id) as (
+        values (0), (1), (2), (3), (100), (1000), (1001)
+    )
+    insert or ignore into partner_voice(part_id, voice_id)
+    select
+        v.part_id, voice.voice_id
+    from
+        part_voice v, voice
+    where
+        not exists (select * from partner_voice);
+"#;
+
+// One row for each song whose download URLs are given to user.
+pub const CREATE_DOWNLOAD_RECORD: &str = r#"
+    create table if not exists download_record (
+        user_id integer not null,
+        song_id text not null,
+        download_at integer not null
+    );
+    create index if not exists download_record_user on download_record(user_id, download_at);
+"#;
+
+// Every game progress backup uploaded by users, `data_backup` holds the one
+// served to client.
+pub const CREATE_DATA_BACKUP_HISTORY: &str = r#"
+    create table if not exists data_backup_history (
+        backup_id integer primary key autoincrement,
+        user_id integer not null,
+        version integer,
+        unlocklist text,
+        installid text,
+        devicemodel_name text,
+        story text,
+        create_at integer
+    );
+    create index if not exists data_backup_history_user on data_backup_history(user_id, backup_id);
+"#;
+
+// Price of songs in single list, singles without a row here are free.
+pub const CREATE_SINGLE_PRICE: &str = r#"
+    create table if not exists single_price (
+        song_id text primary key,
+        price integer not null default 0,
+        orig_price integer not null default 0,
+        discount_from integer not null default 0,
+        discount_to integer not null default 0
+    );
+"#;
+
+// Names of `DATA_MIGRATIONS` already applied to database.
+pub const CREATE_DATA_MIGRATION: &str = r#"
+    create table if not exists data_migration (
+        name text primary key,
+        applied_at integer not null
+    );
+"#;
+
+// Columns added to tables of the original database layout, in form of
+// (table, column, column definition).
+pub const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    // '' for friend, 'pending' for friend request waiting for approval,
+    // 'blocked' for user blocked by `user_id`.
+    ("friend_list", "state", "text not null default ''"),
+    ("player", "is_friend_approval_required", "text default ''"),
+    // number of friend slots for `friend_slot` items
+    ("pack_item", "amount", "integer not null default 1"),
+];
+
+// Data changes applied after `ADDED_COLUMNS` in form of (name, statements),
+// each is applied once and recorded in `data_migration`.
+pub const DATA_MIGRATIONS: &[(&str, &str)] = &[
+    // friend slot items used to keep number of slots in `item_id`
+    (
+        "friend_slot_amount",
+        r#"
+        update pack_item set amount = cast(item_id as integer), item_id = ''
+        where item_type = 'friend_slot' and item_id != ''
+        "#,
+    ),
+    // single prices used to be kept in `pack` rows named after song id
+    (
+        "single_price_from_pack",
+        r#"
+        insert or ignore into single_price(song_id, price, orig_price, discount_from, discount_to)
+        select
+            pack_name, price, orig_price, discount_from, discount_to
+        from
+            pack
+        where
+            pack_name in (select song_id from single)
+            and pack_name not in (select pack_name from pack_item);
+        delete from pack
+        where
+            pack_name in (select song_id from single_price)
+            and pack_name not in (select pack_name from pack_item);
+        "#,
+    ),
+    // present expire time used to be in milliseconds
+    (
+        "present_expire_seconds",
+        r#"
+        update present set expire_ts = expire_ts / 1000 where expire_ts > 100000000000
+        "#,
+    ),
+];
+
+pub const CHECK_DATA_MIGRATION_APPLIED: &str = r#"
+    select exists(select * from data_migration where name = ?1)
+"#;
+
+pub const INSERT_DATA_MIGRATION: &str = r#"
+    insert into data_migration(name, applied_at) values(?1, ?2)
+"#;
+
+pub const CHECK_COLUMN_EXISTS: &str = r#"
+    select exists(select * from pragma_table_info(?1) where name = ?2)
+"#;
+
+pub const TABLE_SCHEMAS: &[&str] = &[
+    CREATE_CORE_ITEM,
+    CREATE_PART_UNCAP_CORE,
+    CREATE_PLAYER_FRAGMENT,
+    CREATE_REDEEM_CODE,
+    CREATE_PRESENT,
+    CREATE_PARTNER_VOICE,
+    CREATE_DOWNLOAD_RECORD,
+    CREATE_DATA_BACKUP_HISTORY,
+    CREATE_SINGLE_PRICE,
+    CREATE_DATA_MIGRATION,
+];
+
 // character
 // ============================================================================
 pub const TOGGLE_UNCAP: &str = r#"
@@ -42,7 +259,6 @@ pub const TOGGLE_UNCAP: &str = r#"
 
 pub const CHAR_STATS: &str = r#"
     select
-        ifnull(v.part_id, -1) as "have_voice",
         ifnull(is_uncapped_override, '') as "uncapped_override",
         ifnull(is_uncapped, '') as "uncapped",
         p.char_type,
@@ -60,7 +276,7 @@ pub const CHAR_STATS: &str = r#"
         p.part_id,
         prog_tempest
     from
-        part_stats s, level_exp l, partner p left outer join part_voice v on p.part_id = v.part_id
+        part_stats s, level_exp l, partner p
     where
         s.user_id = ?1
         and s.lv = l.lv
@@ -70,6 +286,75 @@ pub const CHAR_STATS: &str = r#"
 pub const COND_SINGLE_CHAR_STATS: &str = r#"s.part_id = p.part_id and p.part_id = "#;
 pub const COND_ALL_CHAR_STATS: &str = r#"s.part_id = p.part_id"#;
 
+pub const QUERY_PARTNER_GROWTH: &str = r#"
+    select
+        s.part_id,
+        s.exp_val,
+        ifnull(s.is_uncapped, '') as "uncapped",
+        p.frag_1, p.frag_20,
+        p.prog_1, p.prog_20,
+        p.overdrive_1, p.overdrive_20
+    from
+        player, part_stats s, partner p
+    where
+        player.user_id = ?1
+        and s.user_id = player.user_id
+        and s.part_id = player.partner
+        and p.part_id = s.part_id
+"#;
+
+pub const QUERY_LEVEL_EXP: &str = r#"
+    select exp_val from level_exp where lv = ?1
+"#;
+
+pub const QUERY_LEVEL_FOR_EXP: &str = r#"
+    select ifnull(max(lv), 1) from level_exp where exp_val <= ?1 and lv <= ?2
+"#;
+
+pub const UPDATE_PARTNER_GROWTH: &str = r#"
+    update part_stats
+    set exp_val = ?1, lv = ?2, frag = ?3, prog = ?4, overdrive = ?5
+    where user_id = ?6 and part_id = ?7
+"#;
+
+pub const QUERY_PARTNER_VOICE: &str = r#"
+    select part_id, voice_id from partner_voice order by part_id, voice_id
+"#;
+
+pub const QUERY_UNCAP_CORES: &str = r#"
+    select part_id, core_type, amount from part_uncap_core
+"#;
+
+pub const QUERY_PART_UNCAP_CORES: &str = r#"
+    select
+        u.core_type,
+        u.amount as "required",
+        ifnull(c.amount, 0) as "owned"
+    from
+        part_uncap_core u left outer join core_item c
+            on c.user_id = ?1 and c.core_type = u.core_type
+    where
+        u.part_id = ?2
+"#;
+
+pub const QUERY_IS_UNCAPPED: &str = r#"
+    select ifnull(is_uncapped, '') from part_stats where user_id = ?1 and part_id = ?2
+"#;
+
+pub const CONSUME_CORE: &str = r#"
+    update core_item set amount = amount - ?1 where user_id = ?2 and core_type = ?3
+"#;
+
+pub const UNCAP_CHARACTER: &str = r#"
+    update part_stats
+    set is_uncapped = 't', is_uncapped_override = 'f'
+    where user_id = ?1 and part_id = ?2
+"#;
+
+pub const CHECK_CHARACTER_OWNED: &str = r#"
+    select exists(select * from part_stats where user_id = ?1 and part_id = ?2)
+"#;
+
 pub const CHANGE_CHARACTER: &str = r#"
     update player set partner = ?1, is_skill_sealed = ?2 where user_id = ?3
 "#;
@@ -94,6 +379,32 @@ pub const QUERY_DL: &str = r#"
         {song_id_condition}
 "#;
 
+pub const CHECK_SONG_OWNED: &str = r#"
+    select exists(
+        select * from song
+        where
+            song.song_id = ?2
+            and (
+                exists(
+                    select * from pack_purchase_info pur
+                    where pur.user_id = ?1 and pur.pack_name = song.pack_name
+                )
+                or exists(
+                    select * from single_purchase_info pur
+                    where pur.user_id = ?1 and pur.song_id = song.song_id
+                )
+            )
+    )
+"#;
+
+pub const QUERY_AUDIO_CHECKSUM: &str = r#"
+    select checksum from song where song_id = ?1
+"#;
+
+pub const QUERY_CHART_CHECKSUM: &str = r#"
+    select checksum from chart_info where song_id = ?1 and difficulty = ?2
+"#;
+
 pub const PURCHASE_PACK: &str = r#"
     replace into pack_purchase_info(user_id, pack_name) values(?1, ?2)
 "#;
@@ -102,8 +413,251 @@ pub const PURCHASE_SINGLE: &str = r#"
     replace into single_purchase_info(user_id, song_id) values(?1, ?2)
 "#;
 
+pub const CHECK_PACK_OWNED: &str = r#"
+    select exists(select * from pack_purchase_info where user_id = ?1 and pack_name = ?2)
+"#;
+
+pub const CHECK_SINGLE_OWNED: &str = r#"
+    select exists(select * from single_purchase_info where user_id = ?1 and song_id = ?2)
+"#;
+
+pub const QUERY_PACK_PRICE: &str = r#"
+    select
+        case when ?2 between discount_from and discount_to then
+            price
+        else
+            orig_price
+        end as "price"
+    from
+        pack
+    where
+        pack_name = ?1
+"#;
+
+pub const QUERY_SINGLE_PRICE: &str = r#"
+    select
+        case when ?2 between p.discount_from and p.discount_to then
+            ifnull(p.price, 0)
+        else
+            ifnull(p.orig_price, 0)
+        end as "price"
+    from
+        single left outer join single_price p on p.song_id = single.song_id
+    where
+        single.song_id = ?1
+"#;
+
+// Partners and friend slots coming with a pack.
+pub const PACK_BONUS_ITEM: &str = r#"
+    select
+        item_type,
+        item_id,
+        amount
+    from
+        pack_item
+    where
+        pack_name = ?1
+        and item_type in ('character', 'friend_slot')
+"#;
+
+pub const CONSUME_TICKET: &str = r#"
+    update player set ticket = ticket - ?1 where user_id = ?2 and ticket >= ?1
+"#;
+
 pub const GET_SINGLE_LIST: &str = r#"
-    select song_id from single
+    select
+        single.song_id,
+        ifnull(p.price, 0) as "price",
+        ifnull(p.orig_price, 0) as "orig_price",
+        ifnull(p.discount_from, 1491868801000) as "discount_from",
+        ifnull(p.discount_to, 1491868801000) as "discount_to"
+    from
+        single left outer join single_price p on p.song_id = single.song_id
+"#;
+
+pub const RECENT_DOWNLOAD_SONGS: &str = r#"
+    select distinct song_id from download_record where user_id = ?1 and download_at > ?2
+"#;
+
+pub const INSERT_DOWNLOAD_RECORD: &str = r#"
+    insert into download_record(user_id, song_id, download_at) values(?1, ?2, ?3)
+"#;
+
+// song
+// ============================================================================
+pub const CHECK_SONG_EXISTS: &str = r#"
+    select exists(select * from song where song_id = ?1)
+"#;
+
+pub const INSERT_SONG: &str = r#"
+    replace into song(song_id, title_local_en, title_local_ja, pack_name, checksum, remote_dl)
+    values(?1, ?2, ?3, ?4, ?5, ?6)
+"#;
+
+pub const DELETE_SONG_CHARTS: &str = r#"
+    delete from chart_info where song_id = ?1
+"#;
+
+pub const INSERT_CHART: &str = r#"
+    insert into chart_info(song_id, difficulty, rating, checksum, remote_dl)
+    values(?1, ?2, ?3, ?4, ?5)
+"#;
+
+pub const INSERT_SINGLE: &str = r#"
+    insert or ignore into single(song_id) values(?1)
+"#;
+
+pub const INSERT_SINGLE_PRICE: &str = r#"
+    replace into single_price(song_id, price, orig_price) values(?1, ?2, ?2)
+"#;
+
+// item
+// ============================================================================
+pub const CHECK_PACK_EXISTS: &str = r#"
+    select exists(select * from pack where pack_name = ?1)
+"#;
+
+pub const CHECK_SINGLE_EXISTS: &str = r#"
+    select exists(select * from single where song_id = ?1)
+"#;
+
+pub const CHECK_PARTNER_EXISTS: &str = r#"
+    select exists(select * from partner where part_id = ?1)
+"#;
+
+pub const GRANT_PACK: &str = r#"
+    insert or ignore into pack_purchase_info(user_id, pack_name) values(?1, ?2)
+"#;
+
+pub const GRANT_SINGLE: &str = r#"
+    insert or ignore into single_purchase_info(user_id, song_id) values(?1, ?2)
+"#;
+
+pub const GRANT_CHARACTER: &str = r#"
+    insert or ignore into part_stats(
+        user_id,
+        part_id,
+        is_uncapped_override,
+        is_uncapped,
+        exp_val,
+        overdrive,
+        prog,
+        frag,
+        lv
+    )
+    select
+        ?1 as user_id,
+        part_id,
+        'f' as is_uncapped_override,
+        'f' as is_uncapped,
+        0 as exp_val,
+        overdrive_1 as overdrive,
+        prog_1 as prog,
+        frag_1 as frag,
+        1 as lv
+    from
+        partner
+    where
+        part_id = ?2
+"#;
+
+pub const GRANT_CORE: &str = r#"
+    insert into core_item(user_id, core_type, amount) values(?1, ?2, ?3)
+    on conflict(user_id, core_type) do update set amount = amount + excluded.amount
+"#;
+
+pub const GRANT_TICKET: &str = r#"
+    update player set ticket = ticket + ?1 where user_id = ?2
+"#;
+
+pub const GRANT_FRIEND_SLOT: &str = r#"
+    update player set max_friend = ifnull(max_friend, 50) + ?1 where user_id = ?2
+"#;
+
+pub const GRANT_FRAGMENT: &str = r#"
+    insert into player_fragment(user_id, amount) values(?1, ?2)
+    on conflict(user_id) do update set amount = amount + excluded.amount
+"#;
+
+// redeem
+// ============================================================================
+pub const INSERT_REDEEM_CODE: &str = r#"
+    insert into redeem_code(code, max_use, expire_at) values(?1, ?2, ?3)
+"#;
+
+pub const INSERT_REDEEM_CODE_ITEM: &str = r#"
+    insert into redeem_code_item(code, item_type, item_id, amount) values(?1, ?2, ?3, ?4)
+"#;
+
+pub const QUERY_REDEEM_CODE: &str = r#"
+    select
+        max_use,
+        used_count,
+        ifnull(expire_at, 0) as "expire_at",
+        exists(select * from redeem_record r where r.code = c.code and r.user_id = ?2) as "redeemed"
+    from
+        redeem_code c
+    where
+        code = ?1
+"#;
+
+pub const QUERY_REDEEM_CODE_ITEM: &str = r#"
+    select item_type, item_id, amount from redeem_code_item where code = ?1
+"#;
+
+pub const USE_REDEEM_CODE: &str = r#"
+    update redeem_code set used_count = used_count + 1 where code = ?1
+"#;
+
+pub const INSERT_REDEEM_RECORD: &str = r#"
+    insert into redeem_record(code, user_id, redeem_at) values(?1, ?2, ?3)
+"#;
+
+// present
+// ============================================================================
+pub const QUERY_USER_PRESENT: &str = r#"
+    select
+        p.present_id,
+        p.description,
+        p.expire_ts
+    from
+        present p
+    where
+        (?3 is null or p.present_id = ?3)
+        and (p.expire_ts = 0 or p.expire_ts > ?2)
+        and not exists (
+            select * from present_claim c where c.present_id = p.present_id and c.user_id = ?1
+        )
+        and (
+            not exists (select * from present_target t where t.present_id = p.present_id)
+            or exists (
+                select * from present_target t where t.present_id = p.present_id and t.user_id = ?1
+            )
+        )
+"#;
+
+pub const QUERY_PRESENT_ITEM: &str = r#"
+    select item_type, item_id, amount from present_item where present_id = ?1
+"#;
+
+pub const QUERY_ALL_USER_ID: &str = r#"
+    select user_id from player
+"#;
+
+pub const INSERT_PRESENT: &str = r#"
+    insert into present(present_id, description, expire_ts, create_at) values(?1, ?2, ?3, ?4)
+"#;
+
+pub const INSERT_PRESENT_ITEM: &str = r#"
+    insert into present_item(present_id, item_type, item_id, amount) values(?1, ?2, ?3, ?4)
+"#;
+
+pub const INSERT_PRESENT_TARGET: &str = r#"
+    insert or ignore into present_target(present_id, user_id) values(?1, ?2)
+"#;
+
+pub const INSERT_PRESENT_CLAIM: &str = r#"
+    insert into present_claim(present_id, user_id, claim_at) values(?1, ?2, ?3)
 "#;
 
 // info
@@ -166,40 +720,6 @@ pub const GET_NEW_USER_CODE: &str = r#"
         and user_code >= ?1
 "#;
 
-pub const ADD_CHAR_FOR_NEW_USER: &str = r#"
-    replace into part_stats(
-        user_id,
-        part_id,
-        is_uncapped_override,
-        is_uncapped,
-        exp_val,
-        overdrive,
-        prog,
-        frag,
-        lv
-    )
-    select
-        ?1 as user_id,
-        part_id,
-        'f' as is_uncapped_override,
-        ifnull(can_uncap, 'f') as is_uncapped,
-        CASE WHEN ifnull(can_uncap, 'f') = 't' THEN
-            25000
-        ELSE
-            10000
-        END as exp_val,
-        overdrive_20 as overdrive,
-        prog_20 as prog,
-        frag_20 as frag,
-        CASE WHEN ifnull(can_uncap, 'f') = 't' THEN
-            30
-        ELSE
-            20
-        END as lv
-    from
-        partner
-"#;
-
 pub const SIGN_UP: &str = r#"
     insert into player(
         user_id, last_device_id, email, pwdhash,
@@ -219,6 +739,10 @@ pub const LOGIN: &str = r#"
         and pwdhash = ?2
 "#;
 
+pub const GET_USER_CORES: &str = r#"
+    select core_type, amount from core_item where user_id = ?1 and amount > 0
+"#;
+
 pub const GET_USER_TICKET: &str = r#"
     select ticket from player where user_id = ?1
 "#;
@@ -229,6 +753,7 @@ pub const USER_INFO: &str = r#"
         user_code,
         ifnull(display_name, '') as "display_name",
         ticket,
+        ifnull((select amount from player_fragment f where f.user_id = player.user_id), 0) as "fragment",
         ifnull(partner, 0) as "partner",
         ifnull(is_locked_name_duplicated, '') as "locked",
         ifnull(is_skill_sealed, '') as "skill_sealed",
@@ -240,7 +765,7 @@ pub const USER_INFO: &str = r#"
         ifnull(is_hide_rating, '') as "hide_rating", 
         ifnull(favorite_partner, 0) as "fav_partner",
         recent_score_date,
-        max_friend,
+        ifnull(max_friend, 50) as "max_friend",
         rating,
         join_date,
         ifnull(g.is_aprilfools, '') as "is_aprilfools"
@@ -273,21 +798,86 @@ pub const MINIMUM_USER_INFO: &str = r#"
         and part_stats.part_id = fav_partner
 "#;
 
+// Same columns as `MINIMUM_USER_INFO` plus user id and mutual state, for every
+// friend of user.
+pub const FRIEND_MINIMUM_USER_INFO: &str = r#"
+    select
+        player.user_id,
+        user_name,
+        user_code,
+        ifnull(partner, 0) as "partner",
+        (case when ifnull(favorite_partner, 0) = -1
+        then 0
+        else ifnull(favorite_partner, 0)
+        end) as "fav_partner",
+        ifnull(is_skill_sealed, '') as sealed,
+        ifnull(is_uncapped, '') as "uncapped",
+        ifnull(is_uncapped_override, '') as "uncapped_override",
+        rating,
+        ifnull(is_hide_rating, '') as "hide_rating",
+        join_date,
+        ifnull(f.is_mutual, '') as "is_mutual"
+    from
+        friend_list f, player, part_stats
+    where
+        f.user_id = ?1
+        and f.state = ''
+        and player.user_id = f.friend_id
+        and part_stats.user_id = player.user_id
+        and part_stats.part_id = fav_partner
+"#;
+
+// Latest score of user, along with clear type of user's best score on the
+// same chart.
 pub const USER_MOST_RECENT_SCORE: &str = r#"
     select
-		s.song_id, s.difficulty, s.score,
-		s.shiny_pure, s.pure, s.far, s.lost,
-		s.health, ifnull(s.modifier, 0) modifier,
+        s.user_id,
+        s.song_id, s.difficulty, s.score,
+        s.shiny_pure, s.pure, s.far, s.lost,
+        s.health, ifnull(s.modifier, 0) modifier,
         s.played_date,
-        s.clear_type, s2.clear_type "best_clear_type"
-	from
-		score s, best_score b, score s2
-	where
-		s.user_id = ?1
-		and s.played_date = (select max(played_date) from score)
-		and s.song_id = s2.song_id
-		and b.user_id = ?1
-		and b.played_date = s2.played_date
+        s.clear_type,
+        ifnull((
+            select s2.clear_type from best_score b, score s2
+            where
+                b.user_id = s.user_id
+                and s2.user_id = b.user_id
+                and s2.played_date = b.played_date
+                and s2.song_id = s.song_id
+                and s2.difficulty = s.difficulty
+        ), s.clear_type) as "best_clear_type"
+    from
+        score s
+    where
+        s.user_id = ?1
+        and s.played_date = (select max(played_date) from score where user_id = ?1)
+"#;
+
+// Same as `USER_MOST_RECENT_SCORE`, but for every friend of user.
+pub const FRIEND_MOST_RECENT_SCORE: &str = r#"
+    select
+        s.user_id,
+        s.song_id, s.difficulty, s.score,
+        s.shiny_pure, s.pure, s.far, s.lost,
+        s.health, ifnull(s.modifier, 0) modifier,
+        s.played_date,
+        s.clear_type,
+        ifnull((
+            select s2.clear_type from best_score b, score s2
+            where
+                b.user_id = s.user_id
+                and s2.user_id = b.user_id
+                and s2.played_date = b.played_date
+                and s2.song_id = s.song_id
+                and s2.difficulty = s.difficulty
+        ), s.clear_type) as "best_clear_type"
+    from
+        friend_list f, score s
+    where
+        f.user_id = ?1
+        and f.state = ''
+        and s.user_id = f.friend_id
+        and s.played_date = (select max(played_date) from score where user_id = f.friend_id)
 "#;
 
 pub const SET_FAVORITE_CHARACTER: &str = r#"
@@ -444,12 +1034,15 @@ pub const COMPUTE_R10_AND_B30: &str = r#"
     )
 "#;
 
+// Best scores of user ordered by rating, `?2` is max number of records, -1 for
+// no limit.
 pub const QUERY_BEST_SCORE_FOR_LOOKUP: &str = r#"
     select
         case
             when trim(song.title_local_ja) != '' then song.title_local_ja
             else song.title_local_en
         end as title,
+        s.song_id,
         s.difficulty,
         s.score,
         s.shiny_pure,
@@ -470,7 +1063,7 @@ pub const QUERY_BEST_SCORE_FOR_LOOKUP: &str = r#"
         and s.difficulty = c.difficulty
     order by
         rating desc
-    limit 60;
+    limit ?2;
 "#;
 
 pub const UPDATE_RATING: &str = r#"
@@ -539,6 +1132,51 @@ pub const INSERT_OTHER_BACKUP: &str = r#"
     replace into data_backup values(?1, ?2, ?3, ?4, ?5, ?6, ?7);
 "#;
 
+pub const INSERT_BACKUP_HISTORY: &str = r#"
+    insert into data_backup_history (
+        user_id, version, unlocklist, installid, devicemodel_name, story, create_at
+    ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7);
+"#;
+
+// Keep only the latest `?2` backups of user.
+pub const TRIM_BACKUP_HISTORY: &str = r#"
+    delete from data_backup_history
+    where user_id = ?1
+        and backup_id not in (
+            select backup_id from data_backup_history
+            where user_id = ?1
+            order by backup_id desc
+            limit ?2
+        )
+"#;
+
+pub const QUERY_BACKUP_HISTORY: &str = r#"
+    select
+        h.backup_id,
+        h.version,
+        ifnull(h.devicemodel_name, '') devicemodel_name,
+        h.create_at,
+        exists(
+            select * from data_backup b
+            where b.user_id = h.user_id
+                and b.create_at = h.create_at
+                and b.installid is h.installid
+                and b.unlocklist is h.unlocklist
+                and b.story is h.story
+        ) is_current
+    from
+        data_backup_history h
+    where h.user_id = ?1
+    order by h.backup_id desc
+"#;
+
+pub const RESTORE_BACKUP: &str = r#"
+    replace into data_backup(user_id, version, unlocklist, installid, devicemodel_name, story, create_at)
+    select user_id, version, unlocklist, installid, devicemodel_name, story, create_at
+    from data_backup_history
+    where user_id = ?1 and backup_id = ?2
+"#;
+
 // friend
 // ============================================================================
 pub const GET_FRIEND_ID: &str = r#"
@@ -546,7 +1184,15 @@ pub const GET_FRIEND_ID: &str = r#"
 "#;
 
 pub const CHECK_IF_FRIEND_EXISTS: &str = r#"
-    select exists(select * from friend_list where user_id = ?1 and friend_id = ?2)
+    select exists(select * from friend_list where user_id = ?1 and friend_id = ?2 and state = '')
+"#;
+
+pub const GET_FRIEND_STATE: &str = r#"
+    select state from friend_list where user_id = ?1 and friend_id = ?2
+"#;
+
+pub const CHECK_FRIEND_APPROVAL_REQUIRED: &str = r#"
+    select ifnull(is_friend_approval_required, '') = 't' from player where user_id = ?1
 "#;
 
 // pub const CHECK_IS_MUTUAL: &str = r#"
@@ -554,15 +1200,6 @@ pub const CHECK_IF_FRIEND_EXISTS: &str = r#"
 //         and exists(select * from friend_list where user_id = ?2 and friend_id = ?1)
 // "#;
 
-pub const GET_IS_MUTUAL: &str = r#"
-    select
-        ifnull(is_mutual, '')
-    from
-        friend_list
-    where user_id = ?1
-        and friend_id = ?2
-"#;
-
 pub const SET_MUTUAL: &str = r#"
     update
         friend_list
@@ -581,14 +1218,212 @@ pub const UNSET_MUTUAL: &str = r#"
         or friend_id = ?1 and user_id = ?2
 "#;
 
+pub const CHECK_FRIEND_LIST_FULL: &str = r#"
+    select
+        (select count(*) from friend_list where user_id = ?1 and state != 'blocked')
+            >= ifnull(max_friend, 50)
+    from
+        player
+    where
+        user_id = ?1
+"#;
+
+pub const SET_MAX_FRIEND: &str = r#"
+    update player set max_friend = ?1 where user_code = ?2
+"#;
+
 pub const ADD_FRIEND: &str = r#"
-    replace into friend_list(user_id, friend_id) values(?1, ?2)
+    replace into friend_list(user_id, friend_id, state) values(?1, ?2, ?3)
 "#;
 
 pub const DELETE_FRIEND: &str = r#"
-    delete from friend_list where user_id = ?1 and friend_id = ?2
+    delete from friend_list where user_id = ?1 and friend_id = ?2 and state != 'blocked'
 "#;
 
-pub const GET_ALL_FRIEND_ID: &str = r#"
-    select friend_id from friend_list where user_id = ?1
+pub const ACCEPT_FRIEND_REQUEST: &str = r#"
+    update friend_list set state = '' where user_id = ?1 and friend_id = ?2 and state = 'pending'
+"#;
+
+pub const DELETE_FRIEND_REQUEST: &str = r#"
+    delete from friend_list where user_id = ?1 and friend_id = ?2 and state = 'pending'
+"#;
+
+pub const BLOCK_USER: &str = r#"
+    replace into friend_list(user_id, friend_id, is_mutual, state) values(?1, ?2, 'f', 'blocked')
+"#;
+
+pub const UNBLOCK_USER: &str = r#"
+    delete from friend_list where user_id = ?1 and friend_id = ?2 and state = 'blocked'
+"#;
+
+pub const GET_INCOMING_REQUEST_ID: &str = r#"
+    select user_id from friend_list where friend_id = ?1 and state = 'pending'
+"#;
+
+pub const GET_OUTGOING_REQUEST_ID: &str = r#"
+    select friend_id from friend_list where user_id = ?1 and state = 'pending'
+"#;
+
+pub const GET_BLOCKED_ID: &str = r#"
+    select friend_id from friend_list where user_id = ?1 and state = 'blocked'
+"#;
+
+// archive
+// ============================================================================
+pub const ARCHIVE_PLAYER: &str = r#"
+    select
+        user_name,
+        user_code,
+        ifnull(display_name, '') as "display_name",
+        ifnull(email, '') as "email",
+        ifnull(pwdhash, '') as "pwdhash",
+        ifnull(ticket, 0) as "ticket",
+        ifnull(partner, 0) as "partner",
+        ifnull(is_locked_name_duplicated, '') as "locked",
+        ifnull(is_skill_sealed, '') as "skill_sealed",
+        ifnull(curr_map, '') as "curr_map",
+        ifnull(prog_boost, 0) as "prog_boost",
+        ifnull(stamina, 12) as "stamina",
+        ifnull(next_fragstam_ts, 0) as "next_fragstam_ts",
+        ifnull(max_stamina_ts, 0) as "max_stamina_ts",
+        ifnull(recent_score_date, 0) as "recent_score_date",
+        ifnull(max_friend, 50) as "max_friend",
+        ifnull(rating, 0) as "rating",
+        ifnull(join_date, 0) as "join_date",
+        ifnull(max_stamina_notification_enabled, '') as "stamina_notification",
+        ifnull(is_hide_rating, '') as "hide_rating",
+        ifnull(favorite_partner, 0) as "fav_partner",
+        ifnull(is_friend_approval_required, '') as "approval_required"
+    from
+        player
+    where
+        user_id = ?1
+"#;
+
+pub const ARCHIVE_FRAGMENT: &str = r#"
+    select amount from player_fragment where user_id = ?1
+"#;
+
+pub const ARCHIVE_PACK_PURCHASE: &str = r#"
+    select pack_name from pack_purchase_info where user_id = ?1
+"#;
+
+pub const ARCHIVE_SINGLE_PURCHASE: &str = r#"
+    select song_id from single_purchase_info where user_id = ?1
+"#;
+
+pub const ARCHIVE_PART_STATS: &str = r#"
+    select
+        part_id,
+        ifnull(is_uncapped_override, '') as "uncapped_override",
+        ifnull(is_uncapped, '') as "uncapped",
+        ifnull(exp_val, 0) as "exp_val",
+        ifnull(overdrive, 0) as "overdrive",
+        ifnull(prog, 0) as "prog",
+        ifnull(frag, 0) as "frag",
+        ifnull(lv, 1) as "lv"
+    from
+        part_stats
+    where
+        user_id = ?1
+"#;
+
+pub const ARCHIVE_MAP_PROGRESS: &str = r#"
+    select
+        map_id,
+        ifnull(curr_capture, 0) as "curr_capture",
+        ifnull(curr_position, 0) as "curr_position",
+        ifnull(is_locked, '') as "is_locked"
+    from
+        player_map_prog
+    where
+        user_id = ?1
+"#;
+
+pub const ARCHIVE_SCORES: &str = r#"
+    select
+        s.played_date,
+        s.song_id,
+        s.difficulty,
+        s.score,
+        s.shiny_pure,
+        s.pure,
+        s.far,
+        s.lost,
+        s.rating,
+        s.health,
+        ifnull(s.modifier, 0) as "modifier",
+        s.clear_type,
+        exists(
+            select * from best_score b
+            where b.user_id = s.user_id and b.played_date = s.played_date
+        ) as "is_best"
+    from
+        score s
+    where
+        s.user_id = ?1
+    order by
+        s.played_date
+"#;
+
+pub const ARCHIVE_RECENT_SCORES: &str = r#"
+    select played_date, ifnull(is_recent_10, '') as "is_recent_10" from recent_score where user_id = ?1
+"#;
+
+pub const ARCHIVE_FRIENDS: &str = r#"
+    select
+        p.user_code,
+        ifnull(f.is_mutual, '') as "is_mutual",
+        f.state
+    from
+        friend_list f, player p
+    where
+        f.user_id = ?1
+        and p.user_id = f.friend_id
+"#;
+
+pub const ARCHIVE_BACKUP_HISTORY: &str = r#"
+    select
+        version, unlocklist, installid, devicemodel_name, story, create_at
+    from
+        data_backup_history
+    where
+        user_id = ?1
+    order by
+        backup_id
+"#;
+
+pub const IMPORT_PLAYER: &str = r#"
+    insert into player(
+        user_id, user_name, user_code, display_name, email, pwdhash,
+        ticket, partner, is_locked_name_duplicated, is_skill_sealed, curr_map,
+        prog_boost, stamina, next_fragstam_ts, max_stamina_ts,
+        recent_score_date, max_friend, rating, join_date,
+        max_stamina_notification_enabled, is_hide_rating, favorite_partner,
+        is_friend_approval_required
+    ) values(
+        ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+        ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23
+    )
+"#;
+
+pub const IMPORT_PART_STATS: &str = r#"
+    insert into part_stats(
+        user_id, part_id, is_uncapped_override, is_uncapped,
+        exp_val, overdrive, prog, frag, lv
+    ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+"#;
+
+pub const IMPORT_MAP_PROGRESS: &str = r#"
+    insert into player_map_prog(
+        user_id, map_id, curr_capture, curr_position, is_locked
+    ) values(?1, ?2, ?3, ?4, ?5)
+"#;
+
+pub const IMPORT_SCORE: &str = r#"
+    insert into score (
+        user_id, played_date, song_id, difficulty, score,
+        shiny_pure, pure, far, lost, rating,
+        health, modifier, clear_type
+    ) values(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
 "#;
@@ -1,11 +1,20 @@
 pub mod api;
+pub mod chart;
+mod command;
+mod config;
 pub mod data_access;
+mod logging;
+mod metrics;
+mod server;
+mod song_package;
+mod tls;
 
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use config::Config;
 use data_access::*;
 use lazy_static::lazy_static;
 use log;
@@ -13,45 +22,110 @@ use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::params;
 use serde::{Deserialize, Serialize};
-use simple_logger::SimpleLogger;
 use structopt::StructOpt;
 use warp::Filter;
 
 const STATIC_USER_ID: isize = 1;
 
+/// Flags given here override config file and environment variables.
 #[derive(StructOpt)]
 pub struct Cli {
-    #[structopt(short, long, default_value = "127.0.0.1", help = "IP address of server instance.")]
-    ip: String,
+    #[structopt(short, long, parse(from_os_str), help = "Path to TOML config file.")]
+    config: Option<PathBuf>,
 
-    #[structopt(short, long, default_value = "localhost", help = "Hostname of server instance.")]
-    hostname: String,
+    #[structopt(short, long, help = "IP address of server instance. [default: 127.0.0.1]")]
+    ip: Option<String>,
 
-    #[structopt(short, long, default_value = "8080", help = "Port number used by server instance.")]
-    port: u16,
+    #[structopt(short, long, help = "Hostname of server instance. [default: localhost]")]
+    hostname: Option<String>,
 
-    #[structopt(short, long = "db", default_value = "./ZrcDB.db", help = "Path to SQLite data base file used by server.")]
-    db_path: String,
+    #[structopt(short, long, help = "Port number used by server instance. [default: 8080]")]
+    port: Option<u16>,
 
-    #[structopt(short = "r", long = "root", default_value = "./", help = "Root directory of resource files.")]
-    document_root: String,
-    // final access URL will be http://<hostname>/<prefix-all>/<your-api>
-    #[structopt(long = "prefix-all", default_value = "", help = "URL prefix for all API.")]
-    prefix_all: String,
+    #[structopt(short, long = "db", help = "Path to SQLite data base file used by server. [default: ./ZrcDB.db]")]
+    db_path: Option<String>,
 
-    // final access URL will be http://<hostname>/<prefix-all>/<prefix-static>/<your-filename>
-    #[structopt(long = "prefix-static", default_value = "static", help = "Path prefix for static files.")]
-    prefix_static_file: String,
+    #[structopt(short = "r", long = "root", help = "Root directory of resource files. [default: ./]")]
+    document_root: Option<String>,
 
-    
-    #[structopt(long = "songs-dirname", default_value = "songs", help = "Name of songs directory under document root.")]
-    songs_dirname: String,
+    #[structopt(long = "prefix-all", help = "URL prefix for all API.")]
+    prefix_all: Option<String>,
+
+    #[structopt(long = "prefix-static", help = "Path prefix for static files. [default: static]")]
+    prefix_static_file: Option<String>,
+
+    #[structopt(long = "songs-dirname", help = "Name of songs directory under document root. [default: songs]")]
+    songs_dirname: Option<String>,
+
+    #[structopt(long = "tls-cert", help = "PEM file with certificate chain, HTTPS is served when given with --tls-key.")]
+    tls_cert: Option<String>,
+
+    #[structopt(long = "tls-key", help = "PEM file with private key of certificate.")]
+    tls_key: Option<String>,
+
+    #[structopt(long = "dl-base-url", help = "Public URL of static files directory used in download links, derived from request if not set.")]
+    dl_base_url: Option<String>,
+
+    #[structopt(long = "dl-secret", help = "Key for signing download URLs, a random one is generated on each start if not set.")]
+    dl_secret: Option<String>,
+
+    #[structopt(long = "dl-url-ttl", help = "Seconds before signed download URLs expire. [default: 3600]")]
+    dl_url_ttl: Option<i64>,
+
+    #[structopt(long = "dl-daily-limit", help = "Max number of songs a user can download in 24 hours, 0 for no limit. [default: 0]")]
+    dl_daily_limit: Option<usize>,
+
+    #[structopt(long = "save-history", help = "Number of game progress backups kept for each user, at least 1. [default: 10]")]
+    save_history: Option<usize>,
 
     #[structopt(long = "no-auth", help = "Whether to turn off authentication")]
     is_auth_off: bool,
 
-    #[structopt(long = "log-level", default_value = "info")]
-    log_level: log::LevelFilter
+    #[structopt(long = "log-level", help = "[default: info]")]
+    log_level: Option<String>,
+
+    #[structopt(long = "log-format", help = "One of text, logfmt, json. [default: text]")]
+    log_format: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Option<command::Command>,
+}
+
+impl Cli {
+    /// Overwrite values in `config` with flags given.
+    fn apply_to(&mut self, config: &mut Config) {
+        fn set<T>(target: &mut T, value: &mut Option<T>) {
+            if let Some(value) = value.take() {
+                *target = value;
+            }
+        }
+        set(&mut config.server.ip, &mut self.ip);
+        set(&mut config.server.hostname, &mut self.hostname);
+        set(&mut config.server.port, &mut self.port);
+        set(&mut config.server.prefix_all, &mut self.prefix_all);
+        set(&mut config.server.prefix_static, &mut self.prefix_static_file);
+        set(&mut config.database.path, &mut self.db_path);
+        set(&mut config.resource.document_root, &mut self.document_root);
+        set(&mut config.resource.songs_dirname, &mut self.songs_dirname);
+        if self.tls_cert.is_some() {
+            config.tls.cert_path = self.tls_cert.take();
+        }
+        if self.tls_key.is_some() {
+            config.tls.key_path = self.tls_key.take();
+        }
+        if self.dl_base_url.is_some() {
+            config.dlc.base_url = self.dl_base_url.take();
+        }
+        if self.dl_secret.is_some() {
+            config.dlc.secret = self.dl_secret.take();
+        }
+        set(&mut config.dlc.url_ttl, &mut self.dl_url_ttl);
+        set(&mut config.rate_limit.daily_downloads, &mut self.dl_daily_limit);
+        set(&mut config.save.history, &mut self.save_history);
+        set(&mut config.log.level, &mut self.log_level);
+        set(&mut config.log.format, &mut self.log_format);
+        config.auth.disabled |= self.is_auth_off;
+    }
 }
 
 #[allow(clippy::trivially_copy_pass_by_ref)]
@@ -59,18 +133,37 @@ fn is_zero<T: Into<f64> + Copy>(num: &T) -> bool {
     num.clone().into() == 0.
 }
 
+fn random_secret() -> Vec<u8> {
+    (0..32).map(|_| rand::random::<u8>()).collect()
+}
+
 pub async fn start_serving(argv: Vec<String>) {
-    let cli = Cli::from_iter(argv.iter());
+    let mut cli = Cli::from_iter(argv.iter());
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    cli.apply_to(&mut config);
+    if let Err(e) = config.validate() {
+        eprintln!("invalid config:\n\t{}", e);
+        return;
+    }
 
-    SimpleLogger::new()
-        .with_level(cli.log_level)
-        .init()
-        .unwrap();
+    logging::init(
+        config.log_level().expect("log level validated"),
+        config.log_format().expect("log format validated"),
+        config.log.access,
+    )
+    .unwrap();
+    log::info!("Effective config:\n{}", config.display());
 
-    let db_path = match Path::new(&cli.db_path).canonicalize() {
+    let db_path = match Path::new(&config.database.path).canonicalize() {
         Ok(p) => p,
         Err(e) => {
-            log::error!("{}, {}", cli.db_path, e);
+            log::error!("{}, {}", config.database.path, e);
             return;
         }
     };
@@ -78,38 +171,134 @@ pub async fn start_serving(argv: Vec<String>) {
     let sqlite_pool = r2d2::Pool::new(sqlite_connection_manager)
         .expect("Failed to create r2d2 SQLite connection pool");
     let pool_arc = Arc::new(sqlite_pool);
-    log::info!("Connected to database: {}", cli.db_path);
+    log::info!("Connected to database: {}", config.database.path);
+    let init_result = pool_arc
+        .get()
+        .map_err(|e| e.to_string())
+        .and_then(|conn| DBAccessManager::new(conn).init_tables().map_err(|e| e.to_string()));
+    if let Err(e) = init_result {
+        log::error!("failed to initialize database tables, {}", e);
+        return;
+    }
+
+    if let Some(command) = cli.command {
+        let songs_dir = Path::new(&config.resource.document_root).join(&config.resource.songs_dirname);
+        if let Err(e) = command::run(command, pool_arc, &songs_dir) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let document_root = match Path::new(&cli.document_root).canonicalize() {
+    let document_root = match Path::new(&config.resource.document_root).canonicalize() {
         Ok(p) => p,
         Err(e) => {
-            log::error!("{}, {}", cli.document_root, e);
+            log::error!("{}, {}", config.resource.document_root, e);
             return;
         }
     };
-    log::info!("Document root path: {}", cli.document_root);
+    log::info!("Document root path: {}", config.resource.document_root);
+
+    let socket_addr = config.socket_addr().expect("address validated");
+    let tls_config = match config.tls_paths() {
+        Some((cert_path, key_path)) => match tls::TlsConfig::load(cert_path, key_path) {
+            Ok(tls_config) => Some(Arc::new(tls_config)),
+            Err(e) => {
+                log::error!("failed to load TLS certificate, {}", e);
+                return;
+            }
+        },
+        None => None,
+    };
+    let hostname = config.server.hostname.clone();
+
+    let dl_secret = match config.dlc.secret {
+        Some(secret) => secret.into_bytes(),
+        None => {
+            log::warn!("no download URL secret given, links issued before restart will stop working");
+            random_secret()
+        }
+    };
+    let jwt_secret = match config.auth.jwt_secret {
+        Some(secret) => secret.into_bytes(),
+        None if config.auth.disabled => Vec::new(),
+        None => {
+            log::warn!("no JWT secret given, users need to log in again after restart");
+            random_secret()
+        }
+    };
+    let dl_config = api::DLUrlConfig {
+        base_url: config.dlc.base_url,
+        hostname: config.server.hostname,
+        port: config.server.port,
+        is_tls: tls_config.is_some(),
+        prefix_all: config.server.prefix_all.clone(),
+        prefix_static_file: config.server.prefix_static.clone(),
+        songs_dirname: config.resource.songs_dirname,
+        secret: dl_secret,
+        url_ttl: config.dlc.url_ttl,
+        daily_limit: config.rate_limit.daily_downloads,
+    };
+    let auth = api::AuthConfig {
+        is_off: config.auth.disabled,
+        jwt_secret,
+        token_ttl_days: config.auth.token_ttl_days,
+    };
+    let metrics_config = api::MetricsConfig {
+        is_enabled: config.metrics.enabled,
+        path: config.metrics.path,
+        token: config.metrics.token.unwrap_or_default(),
+    };
 
     let routes = api::api_filter(
         pool_arc,
-        cli.hostname,
         document_root,
-        cli.prefix_all,
-        cli.prefix_static_file,
-        cli.songs_dirname,
-        cli.is_auth_off,
+        config.server.prefix_all,
+        config.server.prefix_static,
+        dl_config,
+        config.save.history,
+        auth,
+        metrics_config,
     );
 
-    let socket_addr = match format!("{}:{}", cli.ip, cli.port).parse::<SocketAddr>() {
-        Ok(s) => s,
+    let tls_config = match tls_config {
+        Some(tls_config) => tls_config,
+        None => {
+            let incoming = match server::bind(socket_addr) {
+                Ok(incoming) => incoming,
+                Err(e) => {
+                    log::error!("{}", e);
+                    return;
+                }
+            };
+            log::info!("listening on http://{}", socket_addr);
+            server::serve(routes, incoming).await;
+            return;
+        }
+    };
+    // not served with warp's tls() since it can't swap certificate while running
+    tls::reload_on_hangup(tls_config.clone());
+    if config.tls.redirect_port != 0 {
+        let redirect_addr = SocketAddr::new(socket_addr.ip(), config.tls.redirect_port);
+        let redirect = tls::https_redirect(hostname, socket_addr.port());
+        match server::bind(redirect_addr) {
+            Ok(incoming) => {
+                log::info!("redirecting http://{} to HTTPS", redirect_addr);
+                tokio::spawn(server::serve(redirect, incoming));
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                return;
+            }
+        }
+    }
+    let incoming = match tls::incoming(socket_addr, tls_config).await {
+        Ok(incoming) => incoming,
         Err(e) => {
-            log::error!(
-                "invalid hostname, IP: {}, Port: {}\n\t{}",
-                cli.ip,
-                cli.port,
-                e
-            );
+            log::error!("failed to listen on {}, {}", socket_addr, e);
             return;
         }
     };
-    warp::serve(routes).run(socket_addr).await;
+    log::info!("listening on https://{}", socket_addr);
+    server::serve(routes, hyper::server::accept::from_stream(incoming)).await;
 }
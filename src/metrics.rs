@@ -0,0 +1,117 @@
+use super::*;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge, Encoder,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use std::time::Duration;
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "zrc_http_requests_total",
+        "Number of HTTP requests handled.",
+        &["route", "method", "status"]
+    )
+    .expect("metric can be registered");
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "zrc_http_request_duration_seconds",
+        "Time taken to handle HTTP requests.",
+        &["route", "method"]
+    )
+    .expect("metric can be registered");
+    static ref DB_QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "zrc_db_query_duration_seconds",
+        "Time taken by SQL statements, by statement kind.",
+        &["statement"],
+        vec![0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0]
+    )
+    .expect("metric can be registered");
+    static ref DB_POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "zrc_db_pool_connections",
+        "Number of connections currently held by database pool."
+    )
+    .expect("metric can be registered");
+    static ref DB_POOL_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "zrc_db_pool_idle_connections",
+        "Number of idle connections in database pool."
+    )
+    .expect("metric can be registered");
+    static ref SCORE_UPLOADS: IntCounter =
+        register_int_counter!("zrc_score_uploads_total", "Number of scores uploaded.")
+            .expect("metric can be registered");
+    static ref SIGNUPS: IntCounter = register_int_counter!("zrc_signups_total", "Number of users signed up.")
+        .expect("metric can be registered");
+    static ref LOGINS: IntCounterVec = register_int_counter_vec!(
+        "zrc_logins_total",
+        "Number of login attempts, by result.",
+        &["result"]
+    )
+    .expect("metric can be registered");
+    static ref REJECTIONS: IntCounterVec = register_int_counter_vec!(
+        "zrc_rejections_total",
+        "Number of requests rejected, by error.",
+        &["error"]
+    )
+    .expect("metric can be registered");
+}
+
+pub fn observe_request(route: &str, method: &str, status: u16, elapsed: Duration) {
+    HTTP_REQUESTS
+        .with_label_values(&[route, method, &status.to_string()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[route, method])
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Profiling callback for SQLite connections, statements are labeled by their
+/// first keyword, e.g. `select`, `insert`.
+pub fn observe_query(sql: &str, elapsed: Duration) {
+    let statement = sql
+        .split_whitespace()
+        .next()
+        .map(|s| s.to_lowercase())
+        .filter(|s| s.chars().all(|c| c.is_ascii_alphabetic()))
+        .unwrap_or_else(|| "other".to_string());
+    DB_QUERY_DURATION
+        .with_label_values(&[&statement])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn score_uploaded() {
+    SCORE_UPLOADS.inc();
+}
+
+pub fn signed_up() {
+    SIGNUPS.inc();
+}
+
+pub fn login_attempted(is_success: bool) {
+    let result = if is_success { "success" } else { "failure" };
+    LOGINS.with_label_values(&[result]).inc();
+}
+
+pub fn rejected(error: &str) {
+    REJECTIONS.with_label_values(&[error]).inc();
+}
+
+/// All metrics in Prometheus text format, pool stats are read at this moment.
+pub fn render(pool: &SqlitePool) -> Result<String, String> {
+    // metrics are registered on first use, make sure all of them show up
+    lazy_static::initialize(&HTTP_REQUESTS);
+    lazy_static::initialize(&HTTP_REQUEST_DURATION);
+    lazy_static::initialize(&DB_QUERY_DURATION);
+    lazy_static::initialize(&SCORE_UPLOADS);
+    lazy_static::initialize(&SIGNUPS);
+    lazy_static::initialize(&LOGINS);
+    lazy_static::initialize(&REJECTIONS);
+
+    let state = pool.state();
+    DB_POOL_CONNECTIONS.set(state.connections as i64);
+    DB_POOL_IDLE_CONNECTIONS.set(state.idle_connections as i64);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|e| e.to_string())?;
+    String::from_utf8(buffer).map_err(|e| e.to_string())
+}
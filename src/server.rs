@@ -0,0 +1,136 @@
+use super::*;
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Request, Response};
+use std::convert::Infallible;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use warp::http::HeaderValue;
+
+/// Header carrying request id, taken from request if a proxy has set one, and
+/// always set on response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Connection knowing address of the other end.
+pub trait RemoteAddr {
+    fn remote_addr(&self) -> Option<SocketAddr>;
+}
+
+impl RemoteAddr for AddrStream {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(AddrStream::remote_addr(self))
+    }
+}
+
+impl RemoteAddr for TlsStream<TcpStream> {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().0.peer_addr().ok()
+    }
+}
+
+/// Plain TCP connections accepted on `addr`.
+pub fn bind(addr: SocketAddr) -> Result<AddrIncoming, String> {
+    let mut incoming = AddrIncoming::bind(&addr).map_err(|e| format!("failed to listen on {}, {}", addr, e))?;
+    incoming.set_nodelay(true);
+    Ok(incoming)
+}
+
+/// Serve `filter` on connections from `incoming`. Every request gets an id,
+/// which is attached to logs emitted while handling it and returned in
+/// response header, and is written to access log when done.
+pub async fn serve<F, I>(filter: F, incoming: I)
+where
+    F: Filter<Error = warp::Rejection> + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+    I: Accept,
+    I::Conn: RemoteAddr + AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let service = warp::service(filter);
+    let make_service = make_service_fn(move |conn: &I::Conn| {
+        let remote_addr = conn.remote_addr();
+        let service = service.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle(service.clone(), request, remote_addr)))
+        }
+    });
+    if let Err(e) = hyper::Server::builder(incoming).serve(make_service).await {
+        log::error!("server error, {}", e);
+    }
+}
+
+async fn handle<S>(
+    mut service: S,
+    request: Request<Body>,
+    remote_addr: Option<SocketAddr>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>,
+{
+    let start = Instant::now();
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(logging::new_request_id);
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+
+    let (response, user_id) = logging::with_request(request_id.clone(), async move { service.call(request).await }).await;
+    let mut response = response?;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    logging::access(&logging::AccessEntry {
+        request_id,
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency: start.elapsed(),
+        user_id,
+        remote_addr,
+    });
+    Ok(response)
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn response_id(request_id: Option<&str>) -> String {
+        let mut request = Request::builder().uri("/hello");
+        if let Some(id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, id);
+        }
+        let service = warp::service(warp::path("hello").map(|| "hello"));
+        let response = handle(service, request.body(Body::empty()).unwrap(), None).await.unwrap();
+        response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn request_id_echoed() {
+        assert_eq!(response_id(Some("abc-123_x.y")).await, "abc-123_x.y");
+    }
+
+    #[tokio::test]
+    async fn invalid_request_id_replaced() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for id in [None, Some(""), Some("a b"), Some("a\"b"), Some(too_long.as_str())] {
+            let response_id = response_id(id).await;
+            assert_ne!(Some(response_id.as_str()), id);
+            assert!(is_valid_request_id(&response_id), "{}", response_id);
+        }
+    }
+}
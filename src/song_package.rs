@@ -0,0 +1,205 @@
+use super::*;
+use chart::Chart;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+const META_FILE: &str = "song.json";
+const AUDIO_FILE: &str = "base.ogg";
+const JACKET_FILES: [&str; 2] = ["base.jpg", "base_256.jpg"];
+const MAX_DIFFICULTY: i8 = 3;
+
+#[derive(Deserialize)]
+struct TitleLocalized {
+    en: String,
+    #[serde(default)]
+    ja: String,
+}
+
+/// Content of `song.json` in a song package, e.g.
+/// `{"id": "mysong", "title_localized": {"en": "My Song"}, "set": "base", "ratings": {"0": 2, "1": 5, "2": 8.5}}`.
+/// `set` is name of pack song belongs to, or `single`.
+#[derive(Deserialize)]
+struct SongMeta {
+    id: String,
+    title_localized: TitleLocalized,
+    #[serde(default)]
+    set: Option<String>,
+    #[serde(default = "default_remote_dl")]
+    remote_dl: bool,
+    /// Rating of each chart, keyed by difficulty.
+    ratings: HashMap<i8, f64>,
+}
+
+fn default_remote_dl() -> bool {
+    true
+}
+
+/// A song folder or zip archive containing metadata, audio, charts and jacket
+/// of a song. Files are looked up by name only, so archive can either have
+/// them at its root or inside a single folder.
+pub struct SongPackage {
+    meta: SongMeta,
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl SongPackage {
+    /// Read package from a directory or a `.zip` file, and validate it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let files = if path.is_dir() {
+            read_dir(path)?
+        } else {
+            read_zip(path)?
+        };
+        let meta = files
+            .get(META_FILE)
+            .ok_or_else(|| format!("{} not found in package", META_FILE))?;
+        let meta: SongMeta = serde_json::from_slice(meta)
+            .map_err(|e| format!("invalid {}, {}", META_FILE, e))?;
+        let package = SongPackage { meta, files };
+        package.validate()?;
+        Ok(package)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let id = &self.meta.id;
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid song id '{}', only letters, digits and '_' are allowed", id));
+        }
+        if self.meta.title_localized.en.is_empty() {
+            return Err("English title is missing".to_string());
+        }
+
+        match self.files.get(AUDIO_FILE) {
+            Some(audio) if audio.starts_with(b"OggS") => {}
+            Some(_) => return Err(format!("{} is not an Ogg file", AUDIO_FILE)),
+            None => return Err(format!("{} not found in package", AUDIO_FILE)),
+        }
+
+        if self.meta.ratings.is_empty() {
+            return Err("no chart listed in ratings".to_string());
+        }
+        for (difficulty, rating) in &self.meta.ratings {
+            if !(0..=MAX_DIFFICULTY).contains(difficulty) {
+                return Err(format!("invalid difficulty {}", difficulty));
+            }
+            if *rating <= 0. {
+                return Err(format!("invalid rating {} for difficulty {}", rating, difficulty));
+            }
+            let name = chart_file_name(*difficulty);
+            let chart = self
+                .files
+                .get(&name)
+                .ok_or_else(|| format!("{} not found in package", name))?;
+            let chart = std::str::from_utf8(chart).map_err(|_| format!("{} is not valid UTF-8", name))?;
+            Chart::parse(chart).map_err(|e| format!("invalid chart {}, {}", name, e))?;
+        }
+        for name in self.files.keys().filter(|name| name.ends_with(".aff")) {
+            match name.trim_end_matches(".aff").parse::<i8>() {
+                Ok(difficulty) if self.meta.ratings.contains_key(&difficulty) => {}
+                _ => return Err(format!("no rating given for chart {}", name)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn song_id(&self) -> &str {
+        &self.meta.id
+    }
+
+    /// Pack name given in metadata, `None` if song is a single or no pack is
+    /// given.
+    pub fn pack_name(&self) -> Option<&str> {
+        self.meta.set.as_deref().filter(|set| *set != "single")
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.meta.set.as_deref() == Some("single")
+    }
+
+    /// Database records for this song.
+    pub fn entry(&self, shelf: SongShelf) -> SongEntry {
+        let mut charts: Vec<ChartEntry> = self
+            .meta
+            .ratings
+            .iter()
+            .map(|(difficulty, rating)| ChartEntry {
+                difficulty: *difficulty,
+                rating: *rating,
+                checksum: checksum(&self.files[&chart_file_name(*difficulty)]),
+            })
+            .collect();
+        charts.sort_by_key(|chart| chart.difficulty);
+        SongEntry {
+            song_id: self.meta.id.clone(),
+            title_en: self.meta.title_localized.en.clone(),
+            title_ja: self.meta.title_localized.ja.clone(),
+            shelf,
+            checksum: checksum(&self.files[AUDIO_FILE]),
+            remote_dl: self.meta.remote_dl,
+            charts,
+        }
+    }
+
+    /// Write audio, charts and jacket into `dir`, which is created if missing.
+    pub fn write_files(&self, dir: &Path) -> Result<(), String> {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create {}, {}", dir.display(), e))?;
+        let names = std::iter::once(AUDIO_FILE.to_string())
+            .chain(self.meta.ratings.keys().map(|d| chart_file_name(*d)))
+            .chain(JACKET_FILES.iter().map(|name| name.to_string()));
+        for name in names {
+            if let Some(content) = self.files.get(&name) {
+                let path = dir.join(&name);
+                fs::write(&path, content).map_err(|e| format!("failed to write {}, {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn chart_file_name(difficulty: i8) -> String {
+    format!("{}.aff", difficulty)
+}
+
+fn checksum(content: &[u8]) -> String {
+    format!("{:x}", md5::compute(content))
+}
+
+fn read_dir(path: &Path) -> Result<HashMap<String, Vec<u8>>, String> {
+    let mut files = HashMap::new();
+    let entries = fs::read_dir(path).map_err(|e| format!("failed to read {}, {}", path.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let content = fs::read(&path).map_err(|e| format!("failed to read {}, {}", path.display(), e))?;
+        files.insert(name, content);
+    }
+    Ok(files)
+}
+
+fn read_zip(path: &Path) -> Result<HashMap<String, Vec<u8>>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("failed to open {}, {}", path.display(), e))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| format!("failed to read archive, {}", e))?;
+    let mut files = HashMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| format!("failed to read archive, {}", e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = match entry.enclosed_name().and_then(|p| p.file_name()) {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let mut content = Vec::new();
+        entry
+            .read_to_end(&mut content)
+            .map_err(|e| format!("failed to extract {}, {}", name, e))?;
+        if files.insert(name.clone(), content).is_some() {
+            return Err(format!("more than one {} found in archive", name));
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,269 @@
+use super::*;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use warp::http::header::HOST;
+use warp::http::{StatusCode, Uri};
+use warp::path::FullPath;
+use warp::Reply;
+
+/// Handshakes taking longer than this are dropped, so idle connections don't
+/// pile up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of finished handshakes waiting to be picked up by server.
+const ACCEPT_BACKLOG: usize = 64;
+/// Pause after failing to accept a connection, errors like running out of
+/// file descriptors would otherwise make accept loop spin.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Certificate and key used for HTTPS, can be reloaded from the same paths
+/// while serving.
+///
+/// warp's own `tls()` reads certificate and key once when server starts, so
+/// renewed certificates would need a restart. Connections are accepted with
+/// tokio-rustls here instead, taking current config for each handshake.
+pub struct TlsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsConfig {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, String> {
+        let config = build_server_config(cert_path, key_path)?;
+        Ok(TlsConfig {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// Read certificate and key again, connections accepted afterwards use the
+    /// new ones. Old ones are kept if loading fails.
+    pub fn reload(&self) -> Result<(), String> {
+        let config = build_server_config(&self.cert_path, &self.key_path)?;
+        *self.current.write().map_err(|e| e.to_string())? = Arc::new(config);
+        Ok(())
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        let config = match self.current.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        TlsAcceptor::from(config)
+    }
+}
+
+fn build_server_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, String> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("failed to open {}, {}", path.display(), e))
+    };
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut open(cert_path)?)
+        .map_err(|e| format!("invalid certificate file {}, {}", cert_path.display(), e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("no certificate found in {}", cert_path.display()));
+    }
+    let key = rustls_pemfile::read_all(&mut open(key_path)?)
+        .map_err(|e| format!("invalid key file {}, {}", key_path.display(), e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no PKCS#8 or RSA private key found in {}", key_path.display()))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(certs, key)
+        .map_err(|e| format!("unusable certificate or key, {}", e))?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+/// Stream of TLS connections accepted on `addr`, used in place of warp's
+/// `tls()` so certificate can be reloaded on SIGHUP. Handshakes run in their
+/// own tasks, so a slow client doesn't hold up others.
+pub async fn incoming(
+    addr: SocketAddr,
+    tls: Arc<TlsConfig>,
+) -> io::Result<ReceiverStream<io::Result<TlsStream<TcpStream>>>> {
+    let listener = TcpListener::bind(addr).await?;
+    let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::warn!("failed to accept connection, {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            let acceptor = tls.acceptor();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => log::debug!("TLS handshake with {} failed, {}", peer, e),
+                    Err(_) => log::debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+    Ok(ReceiverStream::new(rx))
+}
+
+/// Reload certificate whenever the process receives SIGHUP, e.g. from a
+/// certificate renewal hook.
+#[cfg(unix)]
+pub fn reload_on_hangup(tls: Arc<TlsConfig>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("failed to listen for SIGHUP, certificate reload is unavailable, {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match tls.reload() {
+                Ok(_) => log::info!("TLS certificate reloaded"),
+                Err(e) => log::error!("failed to reload TLS certificate, still using the old one, {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_hangup(_tls: Arc<TlsConfig>) {
+    log::warn!("certificate reload on SIGHUP is only supported on Unix, restart server to load new certificate");
+}
+
+// ANY /<path>
+/// Redirect every plain HTTP request to the same path on HTTPS port.
+pub fn https_redirect(
+    hostname: String,
+    https_port: u16,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(HOST.as_str())
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(move |host: Option<String>, path: FullPath, query: String| {
+            let host = host
+                .as_deref()
+                .map(strip_port)
+                .filter(|h| !h.is_empty())
+                .unwrap_or(&hostname)
+                .to_string();
+            let authority = match https_port {
+                443 => host,
+                port => format!("{}:{}", host, port),
+            };
+            let query = if query.is_empty() { query } else { format!("?{}", query) };
+            match format!("https://{}{}{}", authority, path.as_str(), query).parse::<Uri>() {
+                Ok(uri) => warp::redirect::permanent(uri).into_response(),
+                Err(_) => StatusCode::BAD_REQUEST.into_response(),
+            }
+        })
+}
+
+/// Host part of a `Host` header value, port in it is the one for plain HTTP.
+fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        // colon inside brackets belongs to IPv6 address
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    fn current(tls: &TlsConfig) -> Arc<ServerConfig> {
+        tls.current.read().unwrap().clone()
+    }
+
+    #[test]
+    fn strip_host_port() {
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+        assert_eq!(strip_port(":8080"), "");
+    }
+
+    async fn redirect_location(https_port: u16, host: Option<&str>, path: &str) -> String {
+        let mut request = warp::test::request().path(path);
+        if let Some(host) = host {
+            request = request.header("host", host);
+        }
+        let response = request.reply(&https_redirect("default.host".to_string(), https_port)).await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        response.headers()["location"].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn redirect() {
+        assert_eq!(
+            redirect_location(8443, Some("example.com:8080"), "/a/b?c=1&d=2").await,
+            "https://example.com:8443/a/b?c=1&d=2"
+        );
+        assert_eq!(redirect_location(443, Some("example.com"), "/a").await, "https://example.com/a");
+        assert_eq!(redirect_location(443, Some("[::1]:80"), "/").await, "https://[::1]/");
+        // configured hostname used without usable host header
+        assert_eq!(redirect_location(443, None, "/a").await, "https://default.host/a");
+        assert_eq!(redirect_location(8443, Some(":80"), "/a").await, "https://default.host:8443/a");
+    }
+
+    #[test]
+    fn reload() {
+        let dir = std::env::temp_dir().join(format!("zrc_tls_reload_{}", std::process::id()));
+        install_fixture(&dir, "cert_a.pem", "key_a.pem");
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let tls = TlsConfig::load(&cert_path, &key_path).unwrap();
+        let first = current(&tls);
+
+        // broken files keep the old certificate
+        std::fs::write(&cert_path, "not a certificate").unwrap();
+        assert!(tls.reload().unwrap_err().contains("no certificate found"));
+        assert!(Arc::ptr_eq(&first, &current(&tls)));
+        install_fixture(&dir, "cert_b.pem", "key_b.pem");
+        std::fs::write(&key_path, "").unwrap();
+        assert!(tls.reload().unwrap_err().contains("no PKCS#8 or RSA private key"));
+        assert!(Arc::ptr_eq(&first, &current(&tls)));
+
+        install_fixture(&dir, "cert_b.pem", "key_b.pem");
+        tls.reload().unwrap();
+        assert!(!Arc::ptr_eq(&first, &current(&tls)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Copy fixture certificate and key to `cert.pem` and `key.pem` in `dir`.
+    fn install_fixture(dir: &Path, cert: &str, key: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::copy(fixture(cert), dir.join("cert.pem")).unwrap();
+        std::fs::copy(fixture(key), dir.join("key.pem")).unwrap();
+    }
+}
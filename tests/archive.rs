@@ -0,0 +1,123 @@
+mod common;
+
+use rusqlite::params;
+use zrc_server::data_access::DBAccessManager;
+
+/// User 1 with code 100000001 having some of everything, friends with user 2
+/// both ways and with users 3 and 4 one way.
+fn setup_source(name: &str) -> common::TestDb {
+    let pool = common::setup_db(name);
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "insert into player (user_id, user_name, user_code, email, pwdhash, ticket, is_hide_rating)
+                values (1, 'alice', 100000001, 'alice@example.com', 'hash', 120, 't');
+            insert into player (user_id, user_name, user_code) values (2, 'bob', 100000002);
+            insert into player (user_id, user_name, user_code) values (3, 'carol', 100000003);
+            insert into player (user_id, user_name, user_code) values (4, 'erin', 100000004);
+            insert into friend_list (user_id, friend_id, is_mutual, state) values (1, 2, 't', '');
+            insert into friend_list (user_id, friend_id, is_mutual, state) values (2, 1, 't', '');
+            insert into friend_list (user_id, friend_id, is_mutual, state) values (1, 3, '', '');
+            insert into friend_list (user_id, friend_id, is_mutual, state) values (1, 4, '', '');
+            insert into part_stats values (1, 0, '', 't', 10, 20, 30, 40, 2);
+            insert into part_stats values (1, 1, '', '', 0, 0, 0, 0, 1);
+            insert into player_map_prog values (1, 'map', 2, 5, 't');
+            insert into pack_purchase_info values (1, 'core');
+            insert into core_item values (1, 'core_generic', 3);
+            insert into score values (1, 1000, 'song', 2, 9800000, 900, 1000, 10, 2, 10.5, 100, 0, 1);
+            insert into score values (1, 2000, 'song', 2, 9900000, 950, 1010, 2, 0, 10.8, 100, 0, 2);
+            insert into best_score values (1, 2000);
+            insert into recent_score values (1, 1000, '');
+            insert into recent_score values (1, 2000, 't');
+            insert into data_backup values (1, 1, '[]', 'install', 'device', '{}', 1234);",
+        )
+        .unwrap();
+    pool
+}
+
+#[test]
+fn export_import_round_trip() {
+    let source = setup_source("archive_source");
+    let archive = DBAccessManager::new(source.get().unwrap()).export_user(1).unwrap();
+    assert_eq!(archive.profile.user_code, 100000001);
+    assert_eq!(archive.friends.len(), 3);
+
+    // user 1 and code 100000001 are taken by someone else, bob is here under
+    // another user id, erin requires approval and carol is missing
+    let target = common::setup_db("archive_target");
+    target
+        .get()
+        .unwrap()
+        .execute_batch(
+            "insert into player (user_id, user_name, user_code) values (1, 'dave', 100000001);
+            insert into player (user_id, user_name, user_code) values (7, 'bob', 100000002);
+            insert into player (user_id, user_name, user_code, is_friend_approval_required)
+                values (8, 'erin', 100000004, 't');",
+        )
+        .unwrap();
+    let report = DBAccessManager::new(target.get().unwrap()).import_user(&archive).unwrap();
+    assert_ne!(report.user_id, 1);
+    assert!(report.code_changed);
+    assert_ne!(report.user_code, 100000001);
+    assert_eq!(report.missing_friends, vec![100000003]);
+    assert_eq!(report.one_sided_friends, vec![100000002]);
+
+    let conn = target.get().unwrap();
+    let user_id = report.user_id;
+    let (name, code, ticket, hide_rating): (String, isize, isize, String) = conn
+        .query_row(
+            "select user_name, user_code, ticket, is_hide_rating from player where user_id = ?1",
+            params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap();
+    assert_eq!((name.as_str(), code, ticket, hide_rating.as_str()), ("alice", report.user_code, 120, "t"));
+
+    // only imported user's own friend list is restored
+    let friends: Vec<(isize, isize, String)> = conn
+        .prepare("select user_id, friend_id, state from friend_list order by user_id, friend_id")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(friends, vec![(user_id, 7, "".to_string()), (user_id, 8, "pending".to_string())]);
+
+    let flags: Vec<(String, String)> = conn
+        .prepare("select is_uncapped_override, is_uncapped from part_stats where user_id = ?1 order by part_id")
+        .unwrap()
+        .query_map(params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(flags, vec![("".to_string(), "t".to_string()), ("".to_string(), "".to_string())]);
+
+    let best: Vec<i64> = conn
+        .prepare("select played_date from best_score where user_id = ?1")
+        .unwrap()
+        .query_map(params![user_id], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(best, vec![2000]);
+
+    // archive of imported user matches the one it is imported from, except
+    // for user code and friends
+    let mut reexported = DBAccessManager::new(target.get().unwrap()).export_user(user_id).unwrap();
+    assert_eq!(reexported.profile.user_code, report.user_code);
+    reexported.profile.user_code = archive.profile.user_code;
+    reexported.exported_at = archive.exported_at;
+    let mut expected = serde_json::to_value(&archive).unwrap();
+    expected["friends"] = serde_json::json!([
+        { "user_code": 100000002, "is_mutual": false, "state": "" },
+        { "user_code": 100000004, "is_mutual": false, "state": "pending" },
+    ]);
+    assert_eq!(serde_json::to_value(&reexported).unwrap(), expected);
+}
+
+#[test]
+fn import_existing_user() {
+    let source = setup_source("archive_existing");
+    let archive = DBAccessManager::new(source.get().unwrap()).export_user(1).unwrap();
+    assert!(DBAccessManager::new(source.get().unwrap()).import_user(&archive).is_err());
+}
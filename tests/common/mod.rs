@@ -0,0 +1,59 @@
+use r2d2_sqlite::SqliteConnectionManager;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use zrc_server::data_access::{DBAccessManager, SqlitePool};
+
+/// Tables of the original database layout used by tests, tables added by
+/// server itself are created by `DBAccessManager::init_tables`.
+const BASE_SCHEMA: &str = r#"
+    create table player (user_id integer primary key, user_name text, user_code integer, display_name text, email text, pwdhash text, last_device_id text, ticket integer default 0, partner integer default 0, is_locked_name_duplicated text, is_skill_sealed text, curr_map text, prog_boost integer default 0, stamina integer default 12, next_fragstam_ts integer default 0, max_stamina_ts integer default 0, max_stamina_notification_enabled text, is_hide_rating text, favorite_partner integer default 0, recent_score_date integer default 0, max_friend integer default 50, rating integer default 0, join_date integer default 0);
+    create table part_voice (part_id integer);
+    create table part_stats (user_id integer, part_id integer, is_uncapped_override text, is_uncapped text, exp_val real, overdrive real, prog real, frag real, lv integer, primary key (user_id, part_id));
+    create table song (song_id text primary key, title_local_en text, title_local_ja text, pack_name text, checksum text, remote_dl text);
+    create table chart_info (song_id text, difficulty integer, rating real, checksum text, remote_dl text, primary key (song_id, difficulty));
+    create table score (user_id integer, played_date integer, song_id text, difficulty integer, score integer, shiny_pure integer, pure integer, far integer, lost integer, rating real, health integer, modifier integer, clear_type integer, primary key (user_id, played_date));
+    create table best_score (user_id integer, played_date integer, primary key (user_id, played_date));
+    create table recent_score (user_id integer, played_date integer, is_recent_10 text, primary key (user_id, played_date));
+    create table data_backup (user_id integer primary key, version integer, unlocklist text, installid text, devicemodel_name text, story text, create_at integer);
+    create table friend_list (user_id integer, friend_id integer, is_mutual text, primary key (user_id, friend_id));
+    create table pack (pack_name text primary key, price integer, orig_price integer, discount_from integer, discount_to integer);
+    create table pack_item (pack_name text, item_id text, item_type text, is_available text);
+    create table single (song_id text primary key);
+    create table pack_purchase_info (user_id integer, pack_name text, primary key (user_id, pack_name));
+    create table single_purchase_info (user_id integer, song_id text, primary key (user_id, song_id));
+    create table player_map_prog (user_id integer, map_id text, curr_capture integer, curr_position integer, is_locked text, primary key (user_id, map_id));
+"#;
+
+/// Database file in temp directory, removed when dropped.
+pub struct TestDb {
+    pool: SqlitePool,
+    path: PathBuf,
+}
+
+impl Deref for TestDb {
+    type Target = SqlitePool;
+
+    fn deref(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Create an empty database in temp directory, named after the test using it.
+pub fn setup_db(name: &str) -> TestDb {
+    let path = std::env::temp_dir().join(format!("zrc_{}_{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file(&path)).unwrap();
+    pool.get().unwrap().execute_batch(BASE_SCHEMA).unwrap();
+    DBAccessManager::new(pool.get().unwrap()).init_tables().unwrap();
+    TestDb {
+        pool: Arc::new(pool),
+        path,
+    }
+}
@@ -0,0 +1,154 @@
+mod common;
+
+use std::path::PathBuf;
+use warp::http::StatusCode;
+use warp::Filter;
+use zrc_server::api::{api_filter, AuthConfig, DLUrlConfig, MetricsConfig};
+use zrc_server::data_access::SqlitePool;
+
+const BASE_URL: &str = "http://localhost/static";
+
+fn dl_config(songs_dirname: &str, daily_limit: usize) -> DLUrlConfig {
+    DLUrlConfig {
+        base_url: Some(BASE_URL.to_string()),
+        hostname: "localhost".to_string(),
+        port: 8080,
+        is_tls: false,
+        prefix_all: String::new(),
+        prefix_static_file: "static".to_string(),
+        songs_dirname: songs_dirname.to_string(),
+        secret: vec![0; 32],
+        url_ttl: 3600,
+        daily_limit,
+    }
+}
+
+fn routes(
+    pool: SqlitePool,
+    document_root: PathBuf,
+    dl_config: DLUrlConfig,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    // authentication off, every request is made as user 1
+    let auth = AuthConfig {
+        is_off: true,
+        jwt_secret: Vec::new(),
+        token_ttl_days: 10,
+    };
+    let metrics_config = MetricsConfig {
+        is_enabled: false,
+        path: "metrics".to_string(),
+        token: String::new(),
+    };
+    api_filter(pool, document_root, String::new(), "static".to_string(), dl_config, 10, auth, metrics_config)
+}
+
+/// Document root in temp directory with songs `s1` and `s2` under
+/// `songs_dirname` and a public file, removed when dropped.
+struct DocumentRoot(PathBuf);
+
+impl DocumentRoot {
+    fn new(name: &str, songs_dirname: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("zrc_{}_{}", name, std::process::id()));
+        for song_id in ["s1", "s2"] {
+            let dir = root.join(songs_dirname).join(song_id);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("base.ogg"), song_id).unwrap();
+        }
+        std::fs::write(root.join("notice.txt"), "hello").unwrap();
+        DocumentRoot(root)
+    }
+}
+
+impl Drop for DocumentRoot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// User 1 owning `s1` through pack `base`, `s2` is in a pack not bought.
+fn setup(name: &str) -> common::TestDb {
+    let pool = common::setup_db(name);
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "insert into player (user_id, user_name, user_code) values (1, 'tester', 1);
+            insert into song values ('s1', 'Song 1', '', 'base', 'c1', 't');
+            insert into song values ('s2', 'Song 2', '', 'extra', 'c2', 't');
+            insert into chart_info values ('s1', 2, 9.5, 'c1-2', 't');
+            insert into chart_info values ('s2', 2, 9.5, 'c2-2', 't');
+            insert into pack_purchase_info values (1, 'base');",
+        )
+        .unwrap();
+    pool
+}
+
+async fn fetch(
+    pool: &SqlitePool,
+    root: &DocumentRoot,
+    dl_config: &DLUrlConfig,
+    path: &str,
+) -> StatusCode {
+    warp::test::request()
+        .path(path)
+        .reply(&routes(pool.clone(), root.0.clone(), dl_config.clone()))
+        .await
+        .status()
+}
+
+fn signed_path(dl_config: &DLUrlConfig, path: &str) -> String {
+    dl_config.signed_url(BASE_URL, path, 1).trim_start_matches("http://localhost").to_string()
+}
+
+#[tokio::test]
+async fn nested_songs_dir() {
+    let pool = setup("dlc_nested");
+    let root = DocumentRoot::new("dlc_nested", "data/songs");
+    let config = dl_config("data/songs", 0);
+
+    assert_eq!(fetch(&pool, &root, &config, "/static/notice.txt").await, StatusCode::OK);
+    for path in [
+        "/static/data/songs/s1/base.ogg",
+        "/static/data//songs/./s1/base.ogg",
+        "/static/data/%73ongs/s1/base.ogg",
+    ] {
+        assert_eq!(fetch(&pool, &root, &config, path).await, StatusCode::FORBIDDEN, "{}", path);
+    }
+    let path = signed_path(&config, "data/songs/s1/base.ogg");
+    assert_eq!(fetch(&pool, &root, &config, &path).await, StatusCode::OK);
+    let path = signed_path(&config, "data/songs/s2/base.ogg");
+    assert_eq!(fetch(&pool, &root, &config, &path).await, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn download_limit_counts_fetched_songs() {
+    let pool = setup("dlc_limit");
+    pool.get()
+        .unwrap()
+        .execute("insert into pack_purchase_info values (1, 'extra')", [])
+        .unwrap();
+    let root = DocumentRoot::new("dlc_limit", "songs");
+    let config = dl_config("songs", 1);
+
+    // asking for URLs of every song takes no quota
+    let res = warp::test::request()
+        .path("/serve/download/me/song?url=true")
+        .reply(&routes(pool.clone(), root.0.clone(), config.clone()))
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+    assert_eq!(body["value"].as_object().unwrap().len(), 2);
+
+    // fetching a song again is free, another song is over the limit
+    let path = signed_path(&config, "songs/s1/base.ogg");
+    assert_eq!(fetch(&pool, &root, &config, &path).await, StatusCode::OK);
+    assert_eq!(fetch(&pool, &root, &config, &path).await, StatusCode::OK);
+    let path = signed_path(&config, "songs/s2/base.ogg");
+    assert_eq!(fetch(&pool, &root, &config, &path).await, StatusCode::TOO_MANY_REQUESTS);
+
+    let records: isize = pool
+        .get()
+        .unwrap()
+        .query_row("select count(*) from download_record", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(records, 1);
+}
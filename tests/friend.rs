@@ -0,0 +1,63 @@
+mod common;
+
+use rusqlite::params;
+use zrc_server::data_access::DBAccessManager;
+
+/// Users 1 to 3 with user code 100 + id, user 1 requires approval of friend
+/// requests.
+fn setup(name: &str) -> common::TestDb {
+    let pool = common::setup_db(name);
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "insert into player (user_id, user_name, user_code, is_friend_approval_required) values (1, 'alice', 101, 't');
+            insert into player (user_id, user_name, user_code) values (2, 'bob', 102);
+            insert into player (user_id, user_name, user_code) values (3, 'carol', 103);",
+        )
+        .unwrap();
+    pool
+}
+
+/// `(state, is_mutual)` of `friend_id` in friend list of `user_id`.
+fn friend_row(pool: &common::TestDb, user_id: isize, friend_id: isize) -> Option<(String, String)> {
+    pool.get()
+        .unwrap()
+        .query_row(
+            "select state, ifnull(is_mutual, '') from friend_list where user_id = ?1 and friend_id = ?2",
+            params![user_id, friend_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .ok()
+}
+
+fn row(state: &str, is_mutual: &str) -> Option<(String, String)> {
+    Some((state.to_string(), is_mutual.to_string()))
+}
+
+#[test]
+fn approval_required() {
+    let pool = setup("friend_approval");
+    DBAccessManager::new(pool.get().unwrap()).add_friend(3, 101).unwrap();
+    assert_eq!(friend_row(&pool, 3, 1), row("pending", ""));
+    assert_eq!(friend_row(&pool, 1, 3), None);
+}
+
+#[test]
+fn adding_back_needs_no_approval() {
+    let pool = setup("friend_add_back");
+    // alice adds bob herself, bob adding her back is not a request
+    DBAccessManager::new(pool.get().unwrap()).add_friend(1, 102).unwrap();
+    assert_eq!(friend_row(&pool, 1, 2), row("", ""));
+    DBAccessManager::new(pool.get().unwrap()).add_friend(2, 101).unwrap();
+    assert_eq!(friend_row(&pool, 2, 1), row("", "t"));
+    assert_eq!(friend_row(&pool, 1, 2), row("", "t"));
+}
+
+#[test]
+fn adding_requester_accepts_request() {
+    let pool = setup("friend_accept");
+    DBAccessManager::new(pool.get().unwrap()).add_friend(3, 101).unwrap();
+    DBAccessManager::new(pool.get().unwrap()).add_friend(1, 103).unwrap();
+    assert_eq!(friend_row(&pool, 3, 1), row("", "t"));
+    assert_eq!(friend_row(&pool, 1, 3), row("", "t"));
+}
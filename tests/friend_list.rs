@@ -0,0 +1,121 @@
+mod common;
+
+use rusqlite::params;
+use std::time::{Duration, Instant};
+use zrc_server::data_access::{DBAccessManager, UserInfoMinimum};
+
+const FRIEND_COUNT: isize = 500;
+
+/// Database with user 1 having `FRIEND_COUNT` friends, every other one of them
+/// mutual and every third one with two scores played.
+fn setup(name: &str) -> common::TestDb {
+    let pool = common::setup_db(name);
+    let conn = pool.get().unwrap();
+
+    conn.execute_batch("begin").unwrap();
+    for user_id in 1..=FRIEND_COUNT + 1 {
+        conn.execute(
+            "insert into player (user_id, user_name, user_code, partner, favorite_partner, rating, join_date)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![user_id, format!("user{}", user_id), 100000000 + user_id, user_id % 7, user_id % 3 - 1, user_id * 3, user_id * 1000],
+        )
+        .unwrap();
+        for part_id in 0..2 {
+            conn.execute(
+                "insert into part_stats (user_id, part_id, is_uncapped_override, is_uncapped) values (?1, ?2, '', ?3)",
+                params![user_id, part_id, if user_id % 2 == 0 { "t" } else { "" }],
+            )
+            .unwrap();
+        }
+        if user_id == 1 {
+            continue;
+        }
+        conn.execute(
+            "insert into friend_list (user_id, friend_id, is_mutual) values (1, ?1, ?2)",
+            params![user_id, if user_id % 2 == 0 { "t" } else { "" }],
+        )
+        .unwrap();
+        if user_id % 3 == 0 {
+            for (played_date, clear_type) in [(user_id * 10, 3), (user_id * 10 + 1, 1)] {
+                conn.execute(
+                    "insert into score values (?1, ?2, 'song', 2, 9800000, 900, 1000, 10, 2, 10.5, 100, 0, ?3)",
+                    params![user_id, played_date, clear_type],
+                )
+                .unwrap();
+            }
+            conn.execute("insert into best_score values (?1, ?2)", params![user_id, user_id * 10]).unwrap();
+        }
+    }
+    conn.execute_batch("commit").unwrap();
+    pool
+}
+
+// Previous approach: one minimum info query and one mutual state query for
+// every friend.
+fn per_friend_list(pool: &common::TestDb, conn: &DBAccessManager) -> Vec<UserInfoMinimum> {
+    let raw = pool.get().unwrap();
+    (2..=FRIEND_COUNT + 1)
+        .map(|friend_id| {
+            let mut info = conn.get_minimum_user_info(friend_id).unwrap();
+            info.is_mutual = raw
+                .query_row(
+                    "select ifnull(is_mutual, '') from friend_list where user_id = 1 and friend_id = ?1",
+                    params![friend_id],
+                    |row| Ok(row.get::<usize, String>(0)? == "t"),
+                )
+                .unwrap();
+            info
+        })
+        .collect()
+}
+
+/// Shortest of a few runs of `f`.
+fn min_time<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..3)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+#[test]
+fn friend_list_matches_per_friend_queries() {
+    let pool = setup("friend_list");
+    let conn = DBAccessManager::new(pool.get().unwrap());
+
+    let mut friends = conn.get_friend_list(1).unwrap();
+    assert_eq!(friends.len() as isize, FRIEND_COUNT);
+    let expected = per_friend_list(&pool, &conn);
+
+    friends.sort_by_key(|info| info.user_id);
+    assert_eq!(
+        serde_json::to_value(&friends).unwrap(),
+        serde_json::to_value(&expected).unwrap()
+    );
+
+    let with_score = friends.iter().find(|info| info.user_id == 3).unwrap();
+    let score = serde_json::to_value(&with_score.recent_score).unwrap();
+    assert_eq!(score[0]["time_played"], 31000);
+    assert_eq!(score[0]["clear_type"], 1);
+    assert_eq!(score[0]["best_clear_type"], 3);
+    assert!(friends.iter().find(|info| info.user_id == 2).unwrap().is_mutual);
+}
+
+/// Timing depends on machine load, run with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn friend_list_is_faster_than_per_friend_queries() {
+    let pool = setup("friend_list_timing");
+    let conn = DBAccessManager::new(pool.get().unwrap());
+
+    let batch_time = min_time(|| conn.get_friend_list(1).unwrap());
+    let per_friend_time = min_time(|| per_friend_list(&pool, &conn));
+    println!(
+        "{} friends: batch {:?}, per friend {:?}",
+        FRIEND_COUNT, batch_time, per_friend_time
+    );
+    assert!(batch_time < per_friend_time);
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use zrc_server::data_access::DBAccessManager;
+
+fn count(pool: &common::TestDb, sql: &str) -> isize {
+    pool.get().unwrap().query_row(sql, [], |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn single_price_moved_once() {
+    let pool = common::setup_db("migration_single_price");
+    // database from before single prices had their own table
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "insert into single values ('song');
+            insert into pack values ('song', 100, 200, 0, 0);
+            insert into pack values ('base', 300, 300, 0, 0);
+            delete from data_migration where name = 'single_price_from_pack';",
+        )
+        .unwrap();
+    DBAccessManager::new(pool.get().unwrap()).init_tables().unwrap();
+    assert_eq!(count(&pool, "select price from single_price where song_id = 'song'"), 100);
+    assert_eq!(count(&pool, "select count(*) from pack"), 1);
+
+    // rows added afterwards are left alone on later starts
+    pool.get()
+        .unwrap()
+        .execute("insert into pack values ('song', 50, 50, 0, 0)", [])
+        .unwrap();
+    DBAccessManager::new(pool.get().unwrap()).init_tables().unwrap();
+    assert_eq!(count(&pool, "select count(*) from pack"), 2);
+    assert_eq!(count(&pool, "select price from single_price where song_id = 'song'"), 100);
+}
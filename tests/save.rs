@@ -0,0 +1,207 @@
+mod common;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+use warp::Filter;
+use zrc_server::api::{api_filter, AuthConfig, DLUrlConfig, MetricsConfig};
+use zrc_server::data_access::SqlitePool;
+
+const NO_DATA_ON_CLOUD: i64 = 9905;
+const ERROR_DURING_UPDATING_DATA: i64 = 9907;
+
+fn routes(
+    pool: SqlitePool,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let dl_config = DLUrlConfig {
+        base_url: None,
+        hostname: "localhost".to_string(),
+        port: 8080,
+        is_tls: false,
+        prefix_all: String::new(),
+        prefix_static_file: "static".to_string(),
+        songs_dirname: "songs".to_string(),
+        secret: vec![0; 32],
+        url_ttl: 3600,
+        daily_limit: 0,
+    };
+    // authentication off, every request is made as user 1
+    let auth = AuthConfig {
+        is_off: true,
+        jwt_secret: Vec::new(),
+        token_ttl_days: 10,
+    };
+    let metrics_config = MetricsConfig {
+        is_enabled: false,
+        path: "metrics".to_string(),
+        token: String::new(),
+    };
+    api_filter(pool, std::env::temp_dir(), String::new(), "static".to_string(), dl_config, 10, auth, metrics_config)
+}
+
+fn checksum(content: &str) -> String {
+    format!("{:x}", md5::compute(content))
+}
+
+/// Form body of a backup upload, with checksum attached to each section if
+/// `with_checksum` is set.
+fn backup_form(sections: &[(&str, String)], with_checksum: bool) -> String {
+    let mut fields = Vec::new();
+    for (section, content) in sections {
+        fields.push((format!("{}_data", section), content.clone()));
+        if with_checksum {
+            fields.push((format!("{}_checksum", section), checksum(content)));
+        }
+    }
+    fields
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn sample_sections() -> Vec<(&'static str, String)> {
+    vec![
+        ("version", json!({"val": 1}).to_string()),
+        (
+            "scores",
+            json!({"": [{
+                "song_id": "s1", "version": 1, "difficulty": 2, "score": 9_900_000,
+                "shiny_perfect_count": 900, "perfect_count": 1000, "near_count": 3,
+                "miss_count": 1, "health": 100, "modifier": 0,
+                "time_played": 1_600_000_000, "ct": 0
+            }]})
+            .to_string(),
+        ),
+        (
+            "clearlamps",
+            json!({"": [{"song_id": "s1", "difficulty": 2, "clear_type": 1, "ct": 0}]}).to_string(),
+        ),
+        ("unlocklist", json!({"": [{"unlock_key": "s1|2|0", "complete": 1}]}).to_string()),
+        ("installid", json!({"val": "install-id"}).to_string()),
+        ("devicemodelname", json!({"val": "Phone"}).to_string()),
+        ("story", json!({"": [{"ma": 1, "mi": 2, "c": true, "r": false}]}).to_string()),
+    ]
+}
+
+async fn upload(pool: &SqlitePool, body: String) -> (StatusCode, Value) {
+    let res = warp::test::request()
+        .method("POST")
+        .path("/user/me/save")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(body)
+        .reply(&routes(pool.clone()))
+        .await;
+    (res.status(), serde_json::from_slice(res.body()).unwrap())
+}
+
+async fn download(pool: &SqlitePool) -> (StatusCode, Value) {
+    let (status, body) = download_raw(pool).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+async fn download_raw(pool: &SqlitePool) -> (StatusCode, String) {
+    let res = warp::test::request()
+        .path("/user/me/save")
+        .reply(&routes(pool.clone()))
+        .await;
+    (res.status(), String::from_utf8(res.body().to_vec()).unwrap())
+}
+
+/// JSON text of a section as it appears in response body, since re-serializing
+/// parsed value doesn't keep field order.
+fn raw_section<'a>(body: &'a str, section: &str) -> &'a str {
+    let start = body.find(&format!("\"{}\":{{", section)).unwrap() + section.len() + 3;
+    let mut values = serde_json::Deserializer::from_str(&body[start..]).into_iter::<Value>();
+    values.next().unwrap().unwrap();
+    &body[start..start + values.byte_offset()]
+}
+
+fn add_chart(pool: &SqlitePool) {
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "insert into player (user_id, user_name, user_code) values (1, 'tester', 1);
+            insert into song values ('s1', 'Song', '', 'base', '', 't');
+            insert into chart_info values ('s1', 2, 9.5, '', 't');",
+        )
+        .unwrap();
+}
+
+#[tokio::test]
+async fn empty_save() {
+    let pool = common::setup_db("save_empty");
+
+    let (status, body) = download(&pool).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error_code"], NO_DATA_ON_CLOUD);
+}
+
+#[tokio::test]
+async fn corrupt_save() {
+    let pool = common::setup_db("save_corrupt");
+    add_chart(&pool);
+
+    // checksum not matching content
+    let mut form = backup_form(&sample_sections(), false);
+    form.push_str("&story_checksum=0123456789abcdef0123456789abcdef");
+    let (status, body) = upload(&pool, form).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error_code"], ERROR_DURING_UPDATING_DATA);
+
+    // checksum given without data
+    let (status, body) = upload(&pool, format!("story_checksum={}", checksum("{}"))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error_code"], ERROR_DURING_UPDATING_DATA);
+
+    // malformed section
+    let (status, body) = upload(&pool, backup_form(&[("story", "{\"\": [".to_string())], false)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error_code"], ERROR_DURING_UPDATING_DATA);
+
+    // rejected uploads leave nothing on cloud
+    let (_, body) = download(&pool).await;
+    assert_eq!(body["error_code"], NO_DATA_ON_CLOUD);
+
+    // corrupted data already on cloud is reported instead of panicking
+    pool.get()
+        .unwrap()
+        .execute(
+            "insert into data_backup values (1, 1, '[]', '', '', 'not json', 0)",
+            [],
+        )
+        .unwrap();
+    let (status, body) = download(&pool).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error_code"], ERROR_DURING_UPDATING_DATA);
+}
+
+#[tokio::test]
+async fn normal_save() {
+    let pool = common::setup_db("save_normal");
+    add_chart(&pool);
+
+    let (status, body) = upload(&pool, backup_form(&sample_sections(), true)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["success"], true);
+
+    let (status, raw) = download_raw(&pool).await;
+    assert_eq!(status, StatusCode::OK, "{}", raw);
+    let body: Value = serde_json::from_str(&raw).unwrap();
+    let data = &body["value"];
+    assert_eq!(data["user_id"], 1);
+    assert_eq!(data["story"][""][0]["mi"], 2);
+    assert_eq!(data["unlocklist"][""][0]["unlock_key"], "s1|2|0");
+    assert_eq!(data["devicemodelname"]["val"], "Phone");
+    assert_eq!(data["scores"][""][0]["score"], 9_900_000);
+    assert_eq!(data["scores"][""][0]["time_played"], 1_600_000_000);
+    assert_eq!(data["clearlamps"][""][0]["clear_type"], 1);
+
+    // checksums match what client computes over each section
+    let checksums = data["checksums"].as_object().unwrap();
+    assert_eq!(checksums.len(), sample_sections().len() + 1);
+    for (section, value) in checksums {
+        assert_eq!(value.as_str().unwrap(), checksum(raw_section(&raw, section)), "{}", section);
+    }
+}